@@ -274,11 +274,52 @@ mod tests {
         })
     }
 
+    // Create a scalar function get_<column>(int) per column that selects the column from the row with the given key, and
+    // check that the values of rows 1 and 2 survive it unchanged. Row 1 must hold a value.
+    fn assert_column_functions(
+        client: &mut pgrx::spi::SpiClient<'_>,
+        table: &str,
+        key: &str,
+        columns: &[(&str, &str)],
+    ) -> Result<(), pgrx::spi::Error> {
+        for (column, return_type) in columns {
+            _ = client.update(
+                &format!(
+                    r#"
+                        create function get_{column}(int) returns {return_type} as $$
+                            from {table}
+                            filter {key} == $1
+                            select {{{column}}}
+                        $$ language plprql;
+                    "#
+                ),
+                None,
+                &[],
+            )?;
+
+            for id in [1, 2] {
+                let expected = Spi::get_one::<String>(&format!(
+                    "select (select {column}::text from {table} where {key} = {id})"
+                ))?;
+                assert!(id != 1 || expected.is_some(), "{column} of row 1 should not be null");
+                assert_eq!(
+                    Spi::get_one::<String>(&format!("select get_{column}({id})::text"))?,
+                    expected,
+                    "{column} of row {id}"
+                );
+            }
+        }
+
+        Ok(())
+    }
+
     #[pg_test]
     fn test_supported_types() -> Result<(), pgrx::spi::Error> {
         Spi::connect_mut(|client| {
             _ = client.update(
                 r#"
+                    create type floatrange as range (subtype = float8);
+
                     create table "supported_types"
                     (
                         "int_" int,
@@ -312,6 +353,26 @@ mod tests {
                         "float4_array_" float4[],
                         "float8_array_" float8[],
                         "text_array_" text[],
+                        "int4range_" int4range,
+                        "int8range_" int8range,
+                        "numrange_" numrange,
+                        "daterange_" daterange,
+                        "tstzrange_" tstzrange,
+                        "floatrange_" floatrange,
+                        "empty_numrange_" numrange,
+                        "empty_tstzrange_" tstzrange,
+                        "bytea_" bytea,
+                        "inet_" inet,
+                        "cidr_" cidr,
+                        "macaddr_" macaddr,
+                        "bit_" bit(4),
+                        "varbit_" varbit,
+                        "bpchar_" char(5),
+                        "name_" name,
+                        "json_" json,
+                        "money_" money,
+                        "timetz_" timetz,
+                        "oid_" oid,
                         primary key ("serial_")
                     );
 
@@ -346,7 +407,27 @@ mod tests {
                         "int8_array_",
                         "float4_array_",
                         "float8_array_",
-                        "text_array_"
+                        "text_array_",
+                        "int4range_",
+                        "int8range_",
+                        "numrange_",
+                        "daterange_",
+                        "tstzrange_",
+                        "floatrange_",
+                        "empty_numrange_",
+                        "empty_tstzrange_",
+                        "bytea_",
+                        "inet_",
+                        "cidr_",
+                        "macaddr_",
+                        "bit_",
+                        "varbit_",
+                        "bpchar_",
+                        "name_",
+                        "json_",
+                        "money_",
+                        "timetz_",
+                        "oid_"
                     )
                     values (
                         0, -- int_
@@ -379,7 +460,27 @@ mod tests {
                         ARRAY[100, 200, 300], -- int8_array_
                         ARRAY[1.1, 2.2, 3.3], -- float4_array_
                         ARRAY[10.1, 20.2, 30.3], -- float8_array_
-                        ARRAY['foo', 'bar', 'baz'] -- text_array_
+                        ARRAY['foo', 'bar', 'baz'], -- text_array_
+                        '[1,10)', -- int4range_
+                        '(,100]', -- int8range_
+                        '[1.5,2.5]', -- numrange_
+                        'empty', -- daterange_
+                        '[2024-01-15 14:30:00+00,)', -- tstzrange_
+                        '(1.5,3.5]', -- floatrange_
+                        'empty', -- empty_numrange_
+                        'empty', -- empty_tstzrange_
+                        '\xdeadbeef', -- bytea_
+                        '192.168.1.5/24', -- inet_
+                        '10.0.0.0/8', -- cidr_
+                        '08:00:2b:01:02:03', -- macaddr_
+                        B'1010', -- bit_
+                        B'10110', -- varbit_
+                        'abc', -- bpchar_
+                        'pg_class', -- name_
+                        '{"b": 1,  "a": [1, 2]}', -- json_
+                        12.34, -- money_
+                        '14:30:00+02', -- timetz_
+                        1259 -- oid_
                     );
 
                     create function get_supported_types(int) returns table(
@@ -413,7 +514,27 @@ mod tests {
                         "int8_array_" int8[],
                         "float4_array_" float4[],
                         "float8_array_" float8[],
-                        "text_array_" text[]
+                        "text_array_" text[],
+                        "int4range_" int4range,
+                        "int8range_" int8range,
+                        "numrange_" numrange,
+                        "daterange_" daterange,
+                        "tstzrange_" tstzrange,
+                        "floatrange_" floatrange,
+                        "empty_numrange_" numrange,
+                        "empty_tstzrange_" tstzrange,
+                        "bytea_" bytea,
+                        "inet_" inet,
+                        "cidr_" cidr,
+                        "macaddr_" macaddr,
+                        "bit_" bit(4),
+                        "varbit_" varbit,
+                        "bpchar_" char(5),
+                        "name_" name,
+                        "json_" json,
+                        "money_" money,
+                        "timetz_" timetz,
+                        "oid_" oid
                     ) as $$
                         from supported_types
                         filter serial_ == $1
@@ -582,6 +703,130 @@ mod tests {
                 ])
             );
 
+            use pgrx::datum::{Range, RangeBound};
+
+            let int4range = supported_types.get::<Range<i32>>(supported_types.column_ordinal("int4range_")?)?;
+            assert_eq!(
+                int4range,
+                Some(Range::new(RangeBound::Inclusive(1), RangeBound::Exclusive(10)))
+            );
+
+            // The upper bound of a discrete range is canonicalized to be exclusive
+            let int8range = supported_types.get::<Range<i64>>(supported_types.column_ordinal("int8range_")?)?;
+            assert_eq!(
+                int8range,
+                Some(Range::new(RangeBound::Infinite, RangeBound::Exclusive(101)))
+            );
+
+            let daterange = supported_types.get::<Range<Date>>(supported_types.column_ordinal("daterange_")?)?;
+            assert_eq!(daterange, Some(Range::empty()));
+
+            // Empty ranges of pass-by-reference element types have no bound values to convert
+            let empty_numrange =
+                supported_types.get::<Range<pgrx::AnyNumeric>>(supported_types.column_ordinal("empty_numrange_")?)?;
+            assert_eq!(empty_numrange, Some(Range::empty()));
+
+            let empty_tstzrange = supported_types
+                .get::<Range<TimestampWithTimeZone>>(supported_types.column_ordinal("empty_tstzrange_")?)?;
+            assert_eq!(empty_tstzrange, Some(Range::empty()));
+
+            let bytea = supported_types.get::<Vec<u8>>(supported_types.column_ordinal("bytea_")?)?;
+            assert_eq!(bytea, Some(vec![0xde, 0xad, 0xbe, 0xef]));
+
+            let inet = supported_types.get::<pgrx::Inet>(supported_types.column_ordinal("inet_")?)?;
+            assert_eq!(inet.map(|inet| inet.0), Some("192.168.1.5/24".to_string()));
+
+            let timetz =
+                supported_types.get::<pgrx::datum::TimeWithTimeZone>(supported_types.column_ordinal("timetz_")?)?;
+            assert!(timetz.is_some());
+
+            let oid = supported_types.get::<pg_sys::Oid>(supported_types.column_ordinal("oid_")?)?;
+            assert_eq!(oid, Some(pg_sys::RelationRelationId));
+
+            // Values survive a scalar function unchanged, including ranges' bound inclusivity, infinite bounds and
+            // emptiness, the padding of bpchar and the formatting of json
+            assert_column_functions(
+                client,
+                "supported_types",
+                "serial_",
+                &[
+                    ("int4range_", "int4range"),
+                    ("int8range_", "int8range"),
+                    ("numrange_", "numrange"),
+                    ("daterange_", "daterange"),
+                    ("tstzrange_", "tstzrange"),
+                    ("floatrange_", "floatrange"),
+                    ("empty_numrange_", "numrange"),
+                    ("empty_tstzrange_", "tstzrange"),
+                    ("bytea_", "bytea"),
+                    ("inet_", "inet"),
+                    ("cidr_", "cidr"),
+                    ("macaddr_", "macaddr"),
+                    ("bit_", "bit(4)"),
+                    ("varbit_", "varbit"),
+                    ("bpchar_", "char(5)"),
+                    ("name_", "name"),
+                    ("json_", "json"),
+                    ("money_", "money"),
+                    ("timetz_", "timetz"),
+                    ("oid_", "oid"),
+                ],
+            )?;
+
+            // xml values can only be created when PostgreSQL is built with libxml
+            let has_libxml =
+                Spi::get_one::<bool>("select setting like '%--with-libxml%' from pg_config where name = 'CONFIGURE'")?;
+            if has_libxml == Some(true) {
+                _ = client.update(
+                    r#"
+                        create function get_xml() returns xml as $$
+                            from supported_types
+                            select {xml_ = s"'<a>text</a>'::xml"}
+                            take 1
+                        $$ language plprql;
+                        "#,
+                    None,
+                    &[],
+                )?;
+
+                assert_eq!(
+                    Spi::get_one::<String>("select get_xml()::text")?,
+                    Some("<a>text</a>".to_string())
+                );
+            }
+
+            #[cfg(not(feature = "pg13"))]
+            {
+                _ = client.update(
+                    r#"
+                        create table multirange_values
+                        (
+                            id int,
+                            int4multirange_ int4multirange,
+                            floatmultirange_ floatmultirange,
+                            empty_multirange_ int4multirange
+                        );
+
+                        insert into multirange_values values
+                            (1, '{[1,3), [5,7]}', '{(1.5,2.5], [4,)}', '{}'),
+                            (2, null, null, null);
+                        "#,
+                    None,
+                    &[],
+                )?;
+
+                assert_column_functions(
+                    client,
+                    "multirange_values",
+                    "id",
+                    &[
+                        ("int4multirange_", "int4multirange"),
+                        ("floatmultirange_", "floatmultirange"),
+                        ("empty_multirange_", "int4multirange"),
+                    ],
+                )?;
+            }
+
             Ok(())
         })
     }
@@ -623,6 +868,9 @@ mod tests {
                         "float4_array_" float4[],
                         "float8_array_" float8[],
                         "text_array_" text[],
+                        "int4range_" int4range,
+                        "numrange_" numrange,
+                        "tstzrange_" tstzrange,
                         primary key ("serial_")
                     );
 
@@ -662,7 +910,10 @@ mod tests {
                         "int8_array_" int8[],
                         "float4_array_" float4[],
                         "float8_array_" float8[],
-                        "text_array_" text[]
+                        "text_array_" text[],
+                        "int4range_" int4range,
+                        "numrange_" numrange,
+                        "tstzrange_" tstzrange
                     ) as $$
                         from null_values
                         filter serial_ == $1
@@ -759,6 +1010,21 @@ mod tests {
                 None
             );
 
+            use pgrx::datum::Range;
+
+            assert_eq!(
+                null_values.get::<Range<i32>>(null_values.column_ordinal("int4range_")?)?,
+                None
+            );
+            assert_eq!(
+                null_values.get::<Range<AnyNumeric>>(null_values.column_ordinal("numrange_")?)?,
+                None
+            );
+            assert_eq!(
+                null_values.get::<Range<TimestampWithTimeZone>>(null_values.column_ordinal("tstzrange_")?)?,
+                None
+            );
+
             // Test SetOf's null handling
             _ = client.update(
                 r#"
@@ -901,6 +1167,19 @@ mod tests {
                 setof_null_values.get::<Vec<Option<String>>>(setof_null_values.column_ordinal("text_array_")?)?,
                 None
             );
+            assert_eq!(
+                setof_null_values.get::<Range<i32>>(setof_null_values.column_ordinal("int4range_")?)?,
+                None
+            );
+            assert_eq!(
+                setof_null_values.get::<Range<AnyNumeric>>(setof_null_values.column_ordinal("numrange_")?)?,
+                None
+            );
+            assert_eq!(
+                setof_null_values
+                    .get::<Range<TimestampWithTimeZone>>(setof_null_values.column_ordinal("tstzrange_")?)?,
+                None
+            );
 
             // Test Scalar's null handling
             _ = client.update(
@@ -933,6 +1212,193 @@ mod tests {
 
             assert_eq!(Spi::get_one::<&str>("select get_null_text()")?, None);
 
+            _ = client.update(
+                r#"
+                    create function get_null_int4range() returns int4range as $$
+                        from null_values
+                        filter int4range_ == null
+                        select { int4range_ }
+                        take(1)
+                    $$ language plprql;
+                    "#,
+                None,
+                &[],
+            );
+
+            assert_eq!(Spi::get_one::<Range<i32>>("select get_null_int4range()")?, None);
+
+            #[cfg(not(feature = "pg13"))]
+            {
+                _ = client.update(
+                    r#"
+                        create function get_null_int4multirange() returns int4multirange as $$
+                            from null_values
+                            filter int4range_ == null
+                            select { int4multirange_ = s"int4range_::int4multirange" }
+                            take(1)
+                        $$ language plprql;
+                        "#,
+                    None,
+                    &[],
+                );
+
+                assert_eq!(
+                    Spi::get_one::<bool>("select get_null_int4multirange() is null")?,
+                    Some(true)
+                );
+            }
+
+            Ok(())
+        })
+    }
+
+    #[pg_test]
+    fn test_return_composite() -> Result<(), pgrx::spi::Error> {
+        Spi::connect_mut(|client| {
+            _ = client.update(include_str!("../sql/starwars.sql"), None, &[])?;
+
+            _ = client.update(
+                r#"
+                    create type person_t as (name text, height int);
+
+                    create function get_person(int) returns person_t as $$
+                        from base.people
+                        filter id == $1
+                        select {name, height}
+                    $$ language plprql;
+
+                    create function get_whole_person(int) returns person_t as $$
+                        from base.people
+                        filter id == $1
+                        select {person = s"ROW(name, height)::person_t"}
+                    $$ language plprql;
+
+                    create function get_name_and_height(id int, out name text, out height numeric) as $$
+                        from base.people
+                        filter id == $1
+                        select {name, height}
+                    $$ language plprql;
+
+                    create function get_height_and_name(id int, out height int, out name text) as $$
+                        from base.people
+                        filter id == $1
+                        select {name, height}
+                    $$ language plprql set plprql.match_columns_by_name = on;
+
+                    create function get_record(int) returns record as $$
+                        from base.people
+                        filter id == $1
+                        select {name, height}
+                    $$ language plprql;
+                    "#,
+                None,
+                &[],
+            )?;
+
+            // A single row is returned as a composite value, both in a from clause and in a target list
+            for query in [
+                "select name, height from get_person(1)",
+                "select (get_person(1)).name, (get_person(1)).height",
+                "select name, height from get_whole_person(1)",
+                "select name, height::int from get_name_and_height(1)",
+                "select name, height from get_height_and_name(1)",
+                "select name, height from get_record(1) as (name text, height int)",
+            ] {
+                assert_eq!(
+                    client.select(query, None, &[])?.first().get_two::<String, i32>()?,
+                    (Some("Luke Skywalker".to_string()), Some(172)),
+                    "{query}"
+                );
+            }
+
+            assert_eq!(
+                Spi::get_one::<String>("select get_record(1)::text")?,
+                Some("(\"Luke Skywalker\",172)".to_string())
+            );
+
+            // No row is returned as null
+            for function in [
+                "get_person",
+                "get_whole_person",
+                "get_name_and_height",
+                "get_height_and_name",
+                "get_record",
+            ] {
+                assert_eq!(
+                    Spi::get_one::<bool>(&format!("select {function}(-1) is null"))?,
+                    Some(true),
+                    "{function}"
+                );
+            }
+
+            Ok(())
+        })
+    }
+
+    #[pg_test]
+    fn test_return_setof_record() -> Result<(), pgrx::spi::Error> {
+        Spi::connect_mut(|client| {
+            _ = client.update(include_str!("../sql/starwars.sql"), None, &[])?;
+
+            _ = client.update(
+                r#"
+                    create function get_people_on(int) returns setof record as $$
+                        from base.people
+                        filter planet_id == $1
+                        select {name, height}
+                        sort name
+                        take 3
+                    $$ language plprql;
+
+                    create function get_names_and_heights(planet int, out name text, out height int)
+                    returns setof record as $$
+                        from base.people
+                        filter planet_id == $1
+                        select {name, height}
+                        sort name
+                        take 3
+                    $$ language plprql;
+                    "#,
+                None,
+                &[],
+            )?;
+
+            // Rows have the columns of the column definition list or of the OUT parameters, and are cast to them
+            for query in [
+                "select name, height from get_people_on(1) as (name text, height int)",
+                "select name, height::int from get_people_on(1) as (name varchar, height numeric)",
+                "select name, height from get_names_and_heights(1)",
+            ] {
+                let people = client
+                    .select(query, None, &[])?
+                    .map(|row| Ok((row.get::<String>(1)?, row.get::<i32>(2)?)))
+                    .collect::<Result<Vec<_>, pgrx::spi::Error>>()?;
+
+                assert_eq!(
+                    people,
+                    vec![
+                        (Some("Anakin Skywalker".to_string()), Some(188)),
+                        (Some("Beru Whitesun lars".to_string()), Some(165)),
+                        (Some("Biggs Darklighter".to_string()), Some(183)),
+                    ],
+                    "{query}"
+                );
+            }
+
+            // The column definition list must match the query's columns
+            _ = client.update(
+                r#"
+                    do $$
+                    begin
+                        perform * from get_people_on(1) as (name text);
+                        raise exception 'mismatched column definition list should have been rejected';
+                    exception when fdw_error then null;
+                    end $$;
+                    "#,
+                None,
+                &[],
+            )?;
+
             Ok(())
         })
     }
@@ -1009,6 +1475,1783 @@ mod tests {
         })
     }
 
+    #[pg_test]
+    fn test_return_record_with_params() -> Result<(), pgrx::spi::Error> {
+        Spi::connect_mut(|client| {
+            _ = client.update(include_str!("../sql/starwars.sql"), None, &[])?;
+
+            let names =
+                |rows: pgrx::spi::SpiTupleTable| rows.map(|row| row.get::<String>(1)).collect::<Result<Vec<_>, _>>();
+
+            // Values are bound as parameters with the types they are passed with
+            let tall_people_on_tatooine = client.select(
+                r#"
+                    select * from
+                    prql_params('from base.people | filter planet_id == $1 && height > $2 | sort name | select {name}', 1, 180)
+                    as (name text);"#,
+                None,
+                &[],
+            )?;
+            assert_eq!(
+                names(tall_people_on_tatooine)?,
+                vec![
+                    Some("Anakin Skywalker".to_string()),
+                    Some("Biggs Darklighter".to_string()),
+                    Some("Cliegg Lars".to_string()),
+                    Some("Darth Vader".to_string())
+                ]
+            );
+
+            // Values are never written into the query, and a single text value is bound like any other
+            let luke = client.select(
+                r#"
+                    select * from
+                    prql_params('from base.people | filter name == $1 | select {name}', 'Luke Skywalker')
+                    as (name text);"#,
+                None,
+                &[],
+            )?;
+            assert_eq!(names(luke)?, vec![Some("Luke Skywalker".to_string())]);
+
+            // Values passed as an array are expanded into its elements, including nulls
+            let luke = client.select(
+                r#"
+                    select * from
+                    prql_params(
+                        'from base.people | filter name == $1 && $2 == null | select {name}',
+                        variadic array['Luke Skywalker', null]
+                    )
+                    as (name text);"#,
+                None,
+                &[],
+            )?;
+            assert_eq!(names(luke)?, vec![Some("Luke Skywalker".to_string())]);
+
+            let injected = client.select(
+                r#"
+                    select * from
+                    prql_params('from base.people | filter name == $1 | select {name}', variadic array['x'' or ''1'' = ''1'])
+                    as (name text);"#,
+                None,
+                &[],
+            )?;
+            assert_eq!(names(injected)?, Vec::<Option<String>>::new());
+
+            // Named values are bound with the types the query gives them, e.g. a timestamp from a JSON string
+            let latecomers = client.select(
+                r#"
+                    select * from
+                    prql(
+                        'from base.people | filter planet_id == $planet && created_date > $after | sort name | select {name}',
+                        '{"planet": 1, "after": "2014-12-11"}'::jsonb
+                    )
+                    as (name text);"#,
+                None,
+                &[],
+            )?;
+            assert_eq!(
+                names(latecomers)?,
+                vec![Some("Cliegg Lars".to_string()), Some("Shmi Skywalker".to_string())]
+            );
+
+            _ = client.update(
+                r#"
+                    do $$
+                    begin
+                        perform * from prql('from base.people | filter name == $name', '{}'::jsonb) as (name text);
+                        raise exception 'query with an undefined parameter was run';
+                    exception when others then
+                        if sqlerrm <> 'No parameter named "name" is given' then
+                            raise;
+                        end if;
+                    end $$;
+
+                    do $$
+                    begin
+                        perform * from prql('from base.people | filter name == $name', '{"name": ["Luke"]}'::jsonb)
+                            as (name text);
+                        raise exception 'array bound to a text parameter was accepted';
+                    exception when others then
+                        if sqlerrm <> 'Parameter "name" is a JSON array or object, but the query uses it as text' then
+                            raise;
+                        end if;
+                    end $$;
+
+                    do $$
+                    begin
+                        perform * from prql('from base.people | filter id == $1 | select {name}', '{}'::jsonb)
+                            as (name text);
+                        raise exception 'query with a positional parameter and no values was run';
+                    exception when others then
+                        if sqlerrm <> 'Parameter $1 is not given; use named parameters with a jsonb argument' then
+                            raise;
+                        end if;
+                    end $$;
+
+                    do $$
+                    begin
+                        perform * from prql(
+                            'from base.people | filter id == $1 && height > $2 && planet_id == $3 | select {name}',
+                            '{"id": 1, "height": 100}'::jsonb
+                        ) as (name text);
+                        raise exception 'query with more positional parameters than values was run';
+                    exception when others then
+                        if sqlerrm <> 'Parameter $3 is not given; use named parameters with a jsonb argument' then
+                            raise;
+                        end if;
+                    end $$;
+                    "#,
+                None,
+                &[],
+            )?;
+
+            Ok(())
+        })
+    }
+
+    #[pg_test]
+    fn test_validator() -> Result<(), pgrx::spi::Error> {
+        Spi::connect_mut(|client| {
+            _ = client.update(include_str!("../sql/starwars.sql"), None, &[])?;
+
+            // Function with invalid PRQL is rejected on creation
+            _ = client.update(
+                r#"
+                    do $do$
+                    begin
+                        create function get_names_with_typo() returns setof text as $$
+                            from base.people
+                            selec {name}
+                        $$ language plprql;
+
+                        raise exception 'function with invalid PRQL was created';
+                    exception
+                        when raise_exception then raise;
+                        when others then null;
+                    end;
+                    $do$;
+                    "#,
+                None,
+                &[],
+            )?;
+
+            assert_eq!(
+                Spi::get_one::<bool>("select exists(select 1 from pg_proc where proname = 'get_names_with_typo')")?,
+                Some(false)
+            );
+
+            // Function whose query returns more columns than declared is rejected on creation
+            _ = client.update(
+                r#"
+                    do $do$
+                    begin
+                        create function get_name_height_and_mass() returns table(name text, height int) as $$
+                            from base.people
+                            select {name, height, mass}
+                        $$ language plprql;
+
+                        raise exception 'function with too many columns was accepted';
+                    exception when others then
+                        if sqlerrm <> 'Function returns 2 columns, but query returns 3 columns' then
+                            raise;
+                        end if;
+                    end;
+                    $do$;
+                    "#,
+                None,
+                &[],
+            )?;
+
+            // Function whose query returns a column that cannot be cast to the declared type is rejected on creation
+            _ = client.update(
+                r#"
+                    do $do$
+                    begin
+                        create function get_population() returns table(name text, population date) as $$
+                            from base.planet
+                            select {name, population}
+                        $$ language plprql;
+
+                        raise exception 'function with bigint column declared as date was accepted';
+                    exception when others then
+                        if sqlerrm <> 'Column 2 ("population") of query has type bigint, but function returns date' then
+                            raise;
+                        end if;
+                    end;
+                    $do$;
+                    "#,
+                None,
+                &[],
+            )?;
+
+            // Function whose query returns a column that cannot be cast to the declared scalar is rejected on creation
+            _ = client.update(
+                r#"
+                    do $do$
+                    begin
+                        create function get_max_population() returns date as $$
+                            from base.planet
+                            aggregate { max_population = max population }
+                        $$ language plprql;
+
+                        raise exception 'function with bigint result declared as date was accepted';
+                    exception when others then
+                        if sqlerrm <> 'Column 1 ("max_population") of query has type bigint, but function returns date' then
+                            raise;
+                        end if;
+                    end;
+                    $do$;
+                    "#,
+                None,
+                &[],
+            )?;
+
+            // Function with invalid PRQL is created when check_function_bodies is off, e.g. when restoring a dump
+            _ = client.update(
+                r#"
+                    set local check_function_bodies = off;
+
+                    create function get_names_with_typo() returns setof text as $$
+                        from base.people
+                        selec {name}
+                    $$ language plprql;
+
+                    reset check_function_bodies;
+                    "#,
+                None,
+                &[],
+            )?;
+
+            assert_eq!(
+                Spi::get_one::<bool>("select exists(select 1 from pg_proc where proname = 'get_names_with_typo')")?,
+                Some(true)
+            );
+
+            Ok(())
+        })
+    }
+
+    #[pg_test]
+    fn test_cache() -> Result<(), pgrx::spi::Error> {
+        Spi::connect_mut(|client| {
+            _ = client.update(include_str!("../sql/starwars.sql"), None, &[])?;
+
+            _ = client.update(
+                r#"
+                    create function get_height(int) returns int as $$
+                        from base.people
+                        filter id == $1
+                        select {height}
+                    $$ language plprql;
+                    "#,
+                None,
+                &[],
+            )?;
+
+            // Function is compiled on the first call and cached for subsequent calls
+            let heights = client
+                .select(
+                    "select get_height(id) from base.people where id in (1, 2, 3) order by id",
+                    None,
+                    &[],
+                )?
+                .map(|row| row.get_datum_by_ordinal(1).unwrap().value::<i32>().unwrap())
+                .collect::<Vec<_>>();
+
+            assert_eq!(heights, vec![Some(172), Some(167), Some(96)]);
+
+            let stats = Spi::get_three::<i64, i64, i64>(
+                "select hits, misses, invalidations from plprql_cache_stats() where function_oid = 'get_height'::regproc",
+            )?;
+
+            assert_eq!(stats, (Some(2), Some(1), Some(0)));
+
+            // Function is recompiled after it has been replaced
+            _ = client.update(
+                r#"
+                    create or replace function get_height(int) returns int as $$
+                        from base.people
+                        filter id == $1
+                        select {height = height + 1}
+                    $$ language plprql;
+                    "#,
+                None,
+                &[],
+            )?;
+
+            assert_eq!(Spi::get_one::<i32>("select get_height(1)")?, Some(173));
+
+            let stats = Spi::get_three::<i64, i64, i64>(
+                "select hits, misses, invalidations from plprql_cache_stats() where function_oid = 'get_height'::regproc",
+            )?;
+
+            assert_eq!(stats, (Some(2), Some(2), Some(1)));
+
+            Ok(())
+        })
+    }
+
+    #[pg_test]
+    fn test_saved_plan() -> Result<(), pgrx::spi::Error> {
+        Spi::connect_mut(|client| {
+            _ = client.update(include_str!("../sql/starwars.sql"), None, &[])?;
+
+            _ = client.update(
+                r#"
+                    create function get_name(int) returns text as $$
+                        from base.people
+                        filter id == $1
+                        select {name}
+                    $$ language plprql;
+                    "#,
+                None,
+                &[],
+            )?;
+
+            // The saved plan is reused with both custom and generic plans
+            for plan_cache_mode in ["force_custom_plan", "force_generic_plan", "auto"] {
+                _ = client.update(&format!("set local plan_cache_mode = {plan_cache_mode}"), None, &[])?;
+
+                let names = client
+                    .select(
+                        "select get_name(id) from base.people where id in (1, 2, 3) order by id",
+                        None,
+                        &[],
+                    )?
+                    .map(|row| row.get_datum_by_ordinal(1).unwrap().value::<String>().unwrap())
+                    .collect::<Vec<_>>();
+
+                assert_eq!(
+                    names,
+                    vec![
+                        Some("Luke Skywalker".into()),
+                        Some("C-3PO".into()),
+                        Some("R2-D2".into())
+                    ]
+                );
+            }
+
+            // Only the first call prepared a plan, later calls reused it
+            let stats = || {
+                Spi::get_three::<i64, i64, i64>(
+                    "select hits, misses, prepares from plprql_cache_stats() where function_oid = 'get_name'::regproc",
+                )
+            };
+            assert_eq!(stats()?, (Some(8), Some(1), Some(1)));
+
+            // The saved plan is revalidated by PostgreSQL when a table it depends on changes, rather than prepared again
+            _ = client.update("alter table base.people alter column name type varchar(100)", None, &[])?;
+
+            assert_eq!(
+                Spi::get_one::<String>("select get_name(1)")?,
+                Some("Luke Skywalker".into())
+            );
+            assert_eq!(stats()?, (Some(9), Some(1), Some(1)));
+
+            Ok(())
+        })
+    }
+
+    #[pg_test]
+    fn test_fetch_size() -> Result<(), pgrx::spi::Error> {
+        Spi::connect_mut(|client| {
+            _ = client.update(include_str!("../sql/starwars.sql"), None, &[])?;
+
+            _ = client.update(
+                r#"
+                    create function get_names() returns setof text as $$
+                        from base.people
+                        sort id
+                        select {name}
+                    $$ language plprql;
+
+                    create function get_names_and_heights() returns table(name text, height int) as $$
+                        from base.people
+                        sort id
+                        select {name, height}
+                    $$ language plprql;
+                    "#,
+                None,
+                &[],
+            )?;
+
+            // Results are fetched in batches, including a final batch that is exactly full
+            for fetch_size in [1, 2, 50, 87, 1000] {
+                _ = client.update(&format!("set local plprql.fetch_size = {fetch_size}"), None, &[])?;
+
+                assert_eq!(Spi::get_one::<i64>("select count(*) from get_names()")?, Some(87));
+                assert_eq!(
+                    Spi::get_one::<i64>("select count(*) from get_names_and_heights()")?,
+                    Some(87)
+                );
+            }
+
+            // The portal is closed when the caller stops early
+            _ = client.update("set local plprql.fetch_size = 2", None, &[])?;
+
+            let names = client
+                .select("select get_names() limit 3", None, &[])?
+                .map(|row| row.get_datum_by_ordinal(1).unwrap().value::<String>().unwrap())
+                .collect::<Vec<_>>();
+
+            assert_eq!(
+                names,
+                vec![
+                    Some("Luke Skywalker".into()),
+                    Some("C-3PO".into()),
+                    Some("R2-D2".into())
+                ]
+            );
+
+            let heights = client
+                .select("select (get_names_and_heights()).height limit 3", None, &[])?
+                .map(|row| row.get_datum_by_ordinal(1).unwrap().value::<i32>().unwrap())
+                .collect::<Vec<_>>();
+
+            assert_eq!(heights, vec![Some(172), Some(167), Some(96)]);
+
+            assert_eq!(Spi::get_one::<i64>("select count(*) from pg_cursors")?, Some(0));
+
+            // Rows are streamed, so the query never reaches the rows it fails on
+            _ = client.update(
+                r#"
+                    create function get_reciprocals() returns table(n int, reciprocal int) as $$
+                        from s"SELECT n, 1 / (10 - n) AS reciprocal FROM generate_series(1, 20) AS n"
+                        select {n, reciprocal}
+                    $$ language plprql;
+                    "#,
+                None,
+                &[],
+            )?;
+
+            assert_eq!(
+                Spi::get_one::<i64>("select sum(n) from (select (get_reciprocals()).n limit 3) as t")?,
+                Some(6)
+            );
+
+            Ok(())
+        })
+    }
+
+    #[pg_test]
+    fn test_materialize() -> Result<(), pgrx::spi::Error> {
+        Spi::connect_mut(|client| {
+            _ = client.update(
+                r#"
+                    create function get_numbers(int) returns setof int as $$
+                        from s"SELECT generate_series(1, $1) AS n"
+                        select {n}
+                    $$ language plprql;
+
+                    create function get_numbers_and_squares(int) returns table(n int, square int) as $$
+                        from s"SELECT generate_series(1, $1) AS n"
+                        select {n, square = n * n}
+                    $$ language plprql;
+                    "#,
+                None,
+                &[],
+            )?;
+
+            _ = client.update("set local plprql.materialize = on", None, &[])?;
+
+            // Results spill to disk when they outgrow work_mem
+            _ = client.update("set local work_mem = '64kB'", None, &[])?;
+
+            assert_eq!(
+                Spi::get_two::<i64, i64>("select count(*), sum(n) from get_numbers(100000) as n")?,
+                (Some(100000), Some(5000050000))
+            );
+
+            assert_eq!(
+                Spi::get_two::<i64, i64>("select count(*), sum(square) from get_numbers_and_squares(1000)")?,
+                (Some(1000), Some(333833500))
+            );
+
+            // Scrollable cursors require random access to the tuplestore
+            _ = client.update(
+                "declare numbers scroll cursor for select * from get_numbers_and_squares(10)",
+                None,
+                &[],
+            )?;
+
+            assert_eq!(Spi::get_one::<i32>("fetch last from numbers")?, Some(10));
+            assert_eq!(
+                Spi::get_two::<i32, i32>("fetch first from numbers")?,
+                (Some(1), Some(1))
+            );
+            assert_eq!(
+                Spi::get_two::<i32, i32>("fetch relative 2 from numbers")?,
+                (Some(3), Some(9))
+            );
+
+            // A materialized function runs its whole query, even when the caller stops early
+            _ = client.update(
+                r#"
+                    create function get_reciprocals() returns table(n int, reciprocal int) as $$
+                        from s"SELECT n, 1 / (10 - n) AS reciprocal FROM generate_series(1, 20) AS n"
+                        select {n, reciprocal}
+                    $$ language plprql;
+
+                    do $$
+                    begin
+                        perform (get_reciprocals()).n limit 3;
+                        raise exception 'The whole query did not run';
+                    exception when others then
+                        if sqlerrm <> 'division by zero' then
+                            raise;
+                        end if;
+                    end $$;
+                    "#,
+                None,
+                &[],
+            )?;
+
+            Ok(())
+        })
+    }
+
+    #[pg_test]
+    fn test_tuple_passthrough() -> Result<(), pgrx::spi::Error> {
+        Spi::connect_mut(|client| {
+            _ = client.update(
+                r#"
+                    create table devices (id int, address inet, location point, firmware bytea);
+
+                    insert into devices values
+                        (1, '192.168.0.1', '(1,2)', '\xdeadbeef'),
+                        (2, null, null, null);
+
+                    create function get_devices() returns table(id int, address inet, location point, firmware bytea) as $$
+                        from devices
+                        sort id
+                    $$ language plprql;
+
+                    create function get_addresses() returns setof inet as $$
+                        from devices
+                        sort id
+                        select {address}
+                    $$ language plprql;
+                    "#,
+                None,
+                &[],
+            )?;
+
+            // Values of types without a conversion are passed through instead of becoming null
+            for query in [
+                "select address::text, location::text, encode(firmware, 'hex') from get_devices() order by id",
+                "select (d).address::text, (d).location::text, encode((d).firmware, 'hex') from (select get_devices() as d) as devices",
+            ] {
+                let devices = client
+                    .select(query, None, &[])?
+                    .map(|row| (row.get::<String>(1), row.get::<String>(2), row.get::<String>(3)))
+                    .map(|(address, location, firmware)| (address.unwrap(), location.unwrap(), firmware.unwrap()))
+                    .collect::<Vec<_>>();
+
+                assert_eq!(
+                    devices,
+                    vec![
+                        (
+                            Some("192.168.0.1/32".to_string()),
+                            Some("(1,2)".to_string()),
+                            Some("deadbeef".to_string())
+                        ),
+                        (None, None, None),
+                    ]
+                );
+            }
+
+            for query in [
+                "select address::text from get_addresses() as address",
+                "select get_addresses()::text",
+            ] {
+                let addresses = client
+                    .select(query, None, &[])?
+                    .map(|row| row.get::<String>(1).unwrap())
+                    .collect::<Vec<_>>();
+
+                assert_eq!(addresses, vec![Some("192.168.0.1/32".to_string()), None]);
+            }
+
+            Ok(())
+        })
+    }
+
+    #[pg_test]
+    fn test_raw_datums() -> Result<(), pgrx::spi::Error> {
+        Spi::connect_mut(|client| {
+            _ = client.update(
+                r#"
+                    create table raw_values
+                    (
+                        id int,
+                        point_ point,
+                        tsvector_ tsvector
+                    );
+
+                    insert into raw_values values
+                        (1, '(1.5,-2)', 'a fat cat'),
+                        (2, null, null);
+                    "#,
+                None,
+                &[],
+            )?;
+
+            // Values of types without a dedicated variant survive a scalar function unchanged
+            assert_column_functions(
+                client,
+                "raw_values",
+                "id",
+                &[("point_", "point"), ("tsvector_", "tsvector")],
+            )?;
+
+            Ok(())
+        })
+    }
+
+    #[pg_test]
+    fn test_composite_types() -> Result<(), pgrx::spi::Error> {
+        Spi::connect_mut(|client| {
+            _ = client.update(
+                r#"
+                    create type customer_t as (id int, name text);
+                    create type totals_t as (orders int, amount numeric, best_customer customer_t);
+
+                    create table accounts (id int, customer customer_t, totals totals_t);
+
+                    insert into accounts values
+                        (1, row(1, 'Alice'), row(3, 120.50, row(1, 'Alice'))),
+                        (2, row(2, 'Bob'), null);
+
+                    create function get_accounts() returns table(customer customer_t, totals totals_t) as $$
+                        from accounts
+                        sort id
+                        select {customer, totals}
+                    $$ language plprql;
+
+                    create function get_totals(int) returns record as $$
+                        from accounts
+                        filter id == $1
+                        select {totals}
+                    $$ language plprql;
+
+                    create function get_id_and_name(int) returns record as $$
+                        from accounts
+                        filter id == $1
+                        select {id_and_name = s"ROW(id, (customer).name)"}
+                    $$ language plprql;
+                    "#,
+                None,
+                &[],
+            )?;
+
+            let accounts = client
+                .select("select customer::text, totals::text from get_accounts()", None, &[])?
+                .map(|row| (row.get::<String>(1).unwrap(), row.get::<String>(2).unwrap()))
+                .collect::<Vec<_>>();
+
+            assert_eq!(
+                accounts,
+                vec![
+                    (
+                        Some("(1,Alice)".to_string()),
+                        Some(r#"(3,120.50,"(1,Alice)")"#.to_string())
+                    ),
+                    (Some("(2,Bob)".to_string()), None),
+                ]
+            );
+
+            assert_eq!(
+                Spi::get_one::<String>(
+                    "select (totals).best_customer.name from get_accounts() where (customer).id = 1"
+                )?,
+                Some("Alice".to_string())
+            );
+
+            // Nested composites
+            assert_eq!(
+                Spi::get_one::<String>("select get_totals(1)::text")?,
+                Some(r#"(3,120.50,"(1,Alice)")"#.to_string())
+            );
+
+            assert_eq!(Spi::get_one::<bool>("select get_totals(2) is null")?, Some(true));
+
+            // Anonymous records
+            assert_eq!(
+                Spi::get_one::<String>("select get_id_and_name(2)::text")?,
+                Some("(2,Bob)".to_string())
+            );
+
+            Ok(())
+        })
+    }
+
+    #[pg_test]
+    fn test_array_types() -> Result<(), pgrx::spi::Error> {
+        Spi::connect_mut(|client| {
+            _ = client.update(
+                r#"
+                    create table array_values
+                    (
+                        id int,
+                        numeric_ numeric[],
+                        date_ date[],
+                        timestamptz_ timestamptz[],
+                        uuid_ uuid[],
+                        jsonb_ jsonb[],
+                        varchar_ varchar[],
+                        matrix_ int[][],
+                        shifted_ int[],
+                        empty_ text[]
+                    );
+
+                    insert into array_values values
+                        (
+                            1,
+                            '{1.5, null, 3}',
+                            '{2024-01-01, 2024-02-29}',
+                            '{"2024-01-01 12:00:00+00"}',
+                            '{a0eebc99-9c0b-4ef8-bb6d-6bb9bd380a11}',
+                            array['{"a": 1}'::jsonb, null],
+                            '{x, "y z"}',
+                            '{{1, 2, 3}, {4, null, 6}}',
+                            '[0:1][2:3]={{1, 2}, {3, 4}}',
+                            '{}'
+                        ),
+                        (2, null, null, null, null, null, null, null, null, null);
+                    "#,
+                None,
+                &[],
+            )?;
+
+            // Arrays of any element type keep their dimensions and lower bounds
+            assert_column_functions(
+                client,
+                "array_values",
+                "id",
+                &[
+                    ("numeric_", "numeric[]"),
+                    ("date_", "date[]"),
+                    ("timestamptz_", "timestamptz[]"),
+                    ("uuid_", "uuid[]"),
+                    ("jsonb_", "jsonb[]"),
+                    ("varchar_", "varchar[]"),
+                    ("matrix_", "int[]"),
+                    ("shifted_", "int[]"),
+                    ("empty_", "text[]"),
+                ],
+            )?;
+
+            assert_eq!(
+                Spi::get_one::<String>("select get_shifted_(1)::text")?,
+                Some("[0:1][2:3]={{1,2},{3,4}}".to_string())
+            );
+
+            Ok(())
+        })
+    }
+
+    #[pg_test]
+    fn test_enum_and_domain_types() -> Result<(), pgrx::spi::Error> {
+        Spi::connect_mut(|client| {
+            _ = client.update(
+                r#"
+                    create type status as enum ('draft', 'active', 'archived');
+                    create domain email as text check (value like '%@%');
+                    create domain positive_int as int check (value > 0);
+                    create domain required_text as text not null;
+
+                    create table contacts
+                    (
+                        id int,
+                        status_ status,
+                        email_ email,
+                        score_ positive_int,
+                        plain_email_ text,
+                        plain_score_ int
+                    );
+
+                    insert into contacts values
+                        (1, 'active', 'leia@alderaan.org', 5, 'not an email', -1),
+                        (2, null, null, null, null, null);
+                    "#,
+                None,
+                &[],
+            )?;
+
+            // Enums and domains survive a scalar function unchanged
+            assert_column_functions(
+                client,
+                "contacts",
+                "id",
+                &[("status_", "status"), ("email_", "email"), ("score_", "positive_int")],
+            )?;
+
+            assert_eq!(
+                Spi::get_one::<bool>("select get_status_(1) = 'active'::status")?,
+                Some(true)
+            );
+
+            // Values of a domain's base type are returned as the domain when they satisfy its constraints
+            _ = client.update(
+                r#"
+                    create function get_domain_values(int) returns table(email_ email, score_ positive_int) as $$
+                        from contacts
+                        filter id == $1
+                        select {email_ = s"email_::text", score_ = s"score_::int"}
+                    $$ language plprql;
+
+                    create function get_plain_email(int) returns email as $$
+                        from contacts
+                        filter id == $1
+                        select {plain_email_}
+                    $$ language plprql;
+
+                    create function get_plain_score(int) returns setof positive_int as $$
+                        from contacts
+                        filter id == $1
+                        select {plain_score_}
+                    $$ language plprql;
+
+                    create function get_required_text(int) returns required_text as $$
+                        from contacts
+                        filter id == $1
+                        select {plain_email_}
+                    $$ language plprql;
+                    "#,
+                None,
+                &[],
+            )?;
+
+            let domain_values = client.select("select * from get_domain_values(1)", None, &[])?.first();
+            assert_eq!(
+                domain_values.get_two::<String, i32>()?,
+                (Some("leia@alderaan.org".to_string()), Some(5))
+            );
+
+            assert_eq!(
+                Spi::get_one::<String>("select get_required_text(1)")?,
+                Some("not an email".to_string())
+            );
+
+            // Values that violate the domain's constraints are rejected
+            for (query, condition) in [
+                ("select get_plain_email(1)", "check_violation"),
+                ("select get_plain_score(1)", "check_violation"),
+                ("select * from get_plain_score(1)", "check_violation"),
+                ("select get_required_text(2)", "not_null_violation"),
+            ] {
+                _ = client.update(
+                    &format!(
+                        r#"
+                            do $do$
+                            begin
+                                perform * from ({query}) as result;
+
+                                raise exception 'value that violates the domain was returned';
+                            exception
+                                when {condition} then null;
+                            end;
+                            $do$;
+                        "#
+                    ),
+                    None,
+                    &[],
+                )?;
+            }
+
+            Ok(())
+        })
+    }
+
+    #[pg_test]
+    fn test_result_casts() -> Result<(), pgrx::spi::Error> {
+        Spi::connect_mut(|client| {
+            _ = client.update(include_str!("../sql/starwars.sql"), None, &[])?;
+
+            // Columns are cast to the declared types with assignment casts, e.g. bigint to int and numeric to float8
+            _ = client.update(
+                r#"
+                    create function get_people_per_planet() returns table(planet_id int, n int, average_height float8) as $$
+                        from base.people
+                        group {planet_id} (aggregate {n = count this, average_height = average height})
+                        sort planet_id
+                    $$ language plprql;
+
+                    create function get_number_of_people() returns int as $$
+                        from base.people
+                        aggregate {count this}
+                    $$ language plprql;
+
+                    create function get_heights() returns setof numeric as $$
+                        from base.people
+                        filter height != null
+                        sort {-height}
+                        select {height}
+                    $$ language plprql;
+
+                    create function get_max_population() returns int as $$
+                        from base.planet
+                        aggregate {max population}
+                    $$ language plprql;
+                    "#,
+                None,
+                &[],
+            )?;
+
+            let (planet_id, n) = client
+                .select("select * from get_people_per_planet() where planet_id = 1", None, &[])?
+                .first()
+                .get_two::<i32, i32>()?;
+            assert_eq!(planet_id, Some(1));
+            assert_eq!(
+                n,
+                Spi::get_one::<i64>("select count(*) from base.people where planet_id = 1")?.map(|n| n as i32)
+            );
+
+            assert_eq!(
+                Spi::get_one::<f64>("select average_height from get_people_per_planet() where planet_id = 1")?,
+                Spi::get_one::<f64>("select avg(height)::float8 from base.people where planet_id = 1")?
+            );
+
+            assert_eq!(
+                Spi::get_one::<i32>("select get_number_of_people()")?,
+                Spi::get_one::<i64>("select count(*) from base.people")?.map(|n| n as i32)
+            );
+
+            assert_eq!(
+                Spi::get_one::<AnyNumeric>("select get_heights() limit 1")?,
+                Spi::get_one::<AnyNumeric>("select max(height)::numeric from base.people")?
+            );
+
+            // Values the cast cannot represent are rejected, just like in an assignment
+            _ = client.update(
+                r#"
+                    do $do$
+                    begin
+                        perform get_max_population();
+
+                        raise exception 'population that does not fit in an int was returned';
+                    exception
+                        when numeric_value_out_of_range then null;
+                    end;
+                    $do$;
+                    "#,
+                None,
+                &[],
+            )?;
+
+            // Columns without an assignment cast to the declared type are rejected when the function is called
+            _ = client.update(
+                r#"
+                    set local check_function_bodies = off;
+
+                    create function get_birth_dates() returns table(name text, birth_date date) as $$
+                        from base.people
+                        select {name, height}
+                    $$ language plprql;
+
+                    reset check_function_bodies;
+
+                    do $do$
+                    begin
+                        perform * from get_birth_dates();
+
+                        raise exception 'height was returned as a date';
+                    exception
+                        when others then
+                            if sqlerrm <> 'Column 2: cannot convert integer to date' then
+                                raise;
+                            end if;
+                    end;
+                    $do$;
+                    "#,
+                None,
+                &[],
+            )?;
+
+            Ok(())
+        })
+    }
+
+    #[pg_test]
+    fn test_match_columns_by_name() -> Result<(), pgrx::spi::Error> {
+        Spi::connect_mut(|client| {
+            _ = client.update(include_str!("../sql/starwars.sql"), None, &[])?;
+
+            // Columns are matched by name when the function sets plprql.match_columns_by_name
+            _ = client.update(
+                r#"
+                    create function get_names_and_heights_by_name() returns table(name text, height int) as $$
+                        from base.people
+                        filter height != null
+                        sort {-height, name}
+                        select {height, name}
+                    $$ language plprql set plprql.match_columns_by_name = on;
+                    "#,
+                None,
+                &[],
+            )?;
+
+            let expected = client
+                .select(
+                    "select name, height from base.people where height is not null order by height desc, name limit 3",
+                    None,
+                    &[],
+                )?
+                .map(|row| Ok((row.get::<String>(1)?, row.get::<i32>(2)?)))
+                .collect::<Result<Vec<_>, pgrx::spi::Error>>()?;
+
+            for query in [
+                "select name, height from get_names_and_heights_by_name() limit 3",
+                "select (get_names_and_heights_by_name()).* limit 3",
+            ] {
+                let names_and_heights = client
+                    .select(query, None, &[])?
+                    .map(|row| Ok((row.get::<String>(1)?, row.get::<i32>(2)?)))
+                    .collect::<Result<Vec<_>, pgrx::spi::Error>>()?;
+                assert_eq!(names_and_heights, expected);
+            }
+
+            // Columns are matched by name when the session sets plprql.match_columns_by_name
+            _ = client.update(
+                r#"
+                    set local plprql.match_columns_by_name = on;
+
+                    create function get_heights_and_names_by_name() returns table(height int, name text) as $$
+                        from base.people
+                        filter height != null
+                        sort {-height, name}
+                        select {name, height}
+                    $$ language plprql;
+                    "#,
+                None,
+                &[],
+            )?;
+
+            let heights_and_names = client
+                .select(
+                    "select name, height from get_heights_and_names_by_name() limit 3",
+                    None,
+                    &[],
+                )?
+                .map(|row| Ok((row.get::<String>(1)?, row.get::<i32>(2)?)))
+                .collect::<Result<Vec<_>, pgrx::spi::Error>>()?;
+            assert_eq!(heights_and_names, expected);
+
+            // Missing and extra columns are rejected
+            for (columns, message) in [
+                (
+                    "{name}",
+                    r#"Function returns column "height", but query does not return it"#,
+                ),
+                (
+                    "{name, height, mass}",
+                    r#"Query returns column "mass", but function does not return it"#,
+                ),
+            ] {
+                _ = client.update(
+                    &format!(
+                        r#"
+                            do $do$
+                            begin
+                                create function get_columns_by_name() returns table(name text, height int) as $$
+                                    from base.people
+                                    select {columns}
+                                $$ language plprql set plprql.match_columns_by_name = on;
+
+                                raise exception 'function with mismatched columns was created';
+                            exception
+                                when others then
+                                    if sqlerrm <> '{message}' then
+                                        raise;
+                                    end if;
+                            end;
+                            $do$;
+                        "#
+                    ),
+                    None,
+                    &[],
+                )?;
+            }
+
+            Ok(())
+        })
+    }
+
+    #[pg_test]
+    fn test_polymorphic_types() -> Result<(), pgrx::spi::Error> {
+        Spi::connect_mut(|client| {
+            // Functions with polymorphic return types pass the validator, which cannot resolve those types
+            _ = client.update(
+                r#"
+                    set local check_function_bodies = on;
+
+                    create function top_n(anyarray, int) returns setof anyelement as $$
+                        from s"SELECT unnest($1) AS value"
+                        sort {-value}
+                        derive {position = row_number this}
+                        filter position <= $2
+                        select {value}
+                    $$ language plprql;
+
+                    create function larger(anyelement, anyelement) returns anyelement as $$
+                        from s"SELECT $1 AS a, $2 AS b"
+                        select {larger = case [a > b => a, true => b]}
+                    $$ language plprql;
+
+                    create function larger_compatible(anycompatible, anycompatible) returns anycompatible as $$
+                        from s"SELECT $1 AS a, $2 AS b"
+                        select {larger = case [a > b => a, true => b]}
+                    $$ language plprql;
+                    "#,
+                None,
+                &[],
+            )?;
+
+            // Arguments and results have the actual types of the call, both in a from clause and in a target list
+            for query in [
+                "select array_agg(n) from top_n(array[3, 1, 4, 1, 5], 3) as n",
+                "select array_agg(n) from (select top_n(array[3, 1, 4, 1, 5], 3) as n) as t",
+            ] {
+                assert_eq!(Spi::get_one::<Vec<i32>>(query)?, Some(vec![5, 4, 3]), "{query}");
+            }
+
+            assert_eq!(
+                Spi::get_one::<Vec<String>>("select array_agg(s) from top_n(array['b', 'c', 'a'], 2) as s")?,
+                Some(vec!["c".to_string(), "b".to_string()])
+            );
+
+            assert_eq!(
+                Spi::get_one::<String>("select pg_typeof(n)::text from top_n(array[1.5, 2.5], 1) as n")?,
+                Some("numeric".to_string())
+            );
+
+            assert_eq!(Spi::get_one::<i32>("select larger(1, 2)")?, Some(2));
+            assert_eq!(
+                Spi::get_one::<String>("select larger('2024-01-01'::date, '2023-01-01'::date)::text")?,
+                Some("2024-01-01".to_string())
+            );
+
+            // Arguments of anycompatible types are promoted to their common type
+            assert_eq!(
+                Spi::get_two::<String, String>(
+                    "select larger_compatible(1, 2.5)::text, pg_typeof(larger_compatible(1, 2.5))::text"
+                )?,
+                (Some("2.5".to_string()), Some("numeric".to_string()))
+            );
+
+            // The function can be called with different types in the same session
+            assert_eq!(
+                Spi::get_one::<String>("select larger('abc'::text, 'abd'::text)")?,
+                Some("abd".to_string())
+            );
+
+            Ok(())
+        })
+    }
+
+    #[pg_test]
+    fn test_variadic_arguments() -> Result<(), pgrx::spi::Error> {
+        Spi::connect_mut(|client| {
+            _ = client.update(include_str!("../sql/starwars.sql"), None, &[])?;
+
+            _ = client.update(
+                r#"
+                    create function get_names(variadic ids int[]) returns setof text as $$
+                        from base.people
+                        filter s"{id} = ANY($1)"
+                        sort name
+                        select {name}
+                    $$ language plprql;
+
+                    create function count_values(variadic anyarray) returns bigint as $$
+                        from s"SELECT unnest($1) AS value"
+                        aggregate {count this}
+                    $$ language plprql;
+                    "#,
+                None,
+                &[],
+            )?;
+
+            // The variadic argument is collected into an array, whether it is given as a list or as an array
+            for query in [
+                "select array_agg(name) from get_names(1, 2, 3) as name",
+                "select array_agg(name) from get_names(variadic array[1, 2, 3]) as name",
+            ] {
+                assert_eq!(
+                    Spi::get_one::<Vec<String>>(query)?,
+                    Some(vec![
+                        "C-3PO".to_string(),
+                        "Luke Skywalker".to_string(),
+                        "R2-D2".to_string()
+                    ]),
+                    "{query}"
+                );
+            }
+
+            assert_eq!(
+                Spi::get_one::<i64>("select count_values('a'::text, 'b', 'c')")?,
+                Some(3)
+            );
+            assert_eq!(
+                Spi::get_one::<i64>("select count_values(variadic array[1, 2])")?,
+                Some(2)
+            );
+
+            // Values passed to variadic "any" are not collected into an array
+            _ = client.update(
+                r#"
+                    do $$
+                    begin
+                        create function count_any(variadic "any") returns bigint as $x$
+                            from s"SELECT 1 AS value"
+                        $x$ language plprql;
+                        raise exception 'variadic "any" should have been rejected';
+                    exception when fdw_error then null;
+                    end $$;
+                    "#,
+                None,
+                &[],
+            )?;
+
+            Ok(())
+        })
+    }
+
+    #[pg_test]
+    fn test_named_arguments() -> Result<(), pgrx::spi::Error> {
+        Spi::connect_mut(|client| {
+            _ = client.update(include_str!("../sql/starwars.sql"), None, &[])?;
+
+            _ = client.update(
+                r#"
+                    create function get_names_on(planet int) returns setof text as $$
+                        from base.people
+                        filter planet_id == $planet
+                        sort name
+                        take 3
+                        select {name}
+                    $$ language plprql;
+
+                    create function get_names_on_and_taller(planet_id int, height int) returns setof text as $$
+                        from base.people
+                        filter planet_id == $planet_id && height > $2
+                        sort name
+                        take 3
+                        select {name}
+                    $$ language plprql;
+
+                    create function get_names_of(variadic ids int[]) returns setof text as $$
+                        from base.people
+                        filter s"{id} = ANY($ids)"
+                        sort name
+                        select {name}
+                    $$ language plprql;
+
+                    create function get_name_with(suffix text) returns text as $$
+                        from base.people
+                        filter id == 1
+                        select {name = s"{name} || $suffix || ' $suffix' || $q$ $suffix$q$"}
+                    $$ language plprql;
+
+                    create function get_quoted_name_with(suffix text) returns text as $$
+                        from s'SELECT name AS "$suffix" FROM base.people WHERE id = 1'
+                        select {name = s'"$suffix" || $suffix'}
+                    $$ language plprql;
+
+                    create function get_escaped_name_with(suffix text) returns text as $$
+                        from base.people
+                        filter id == 1
+                        select {name = s"{name} || $suffix || E' \\'$suffix'"}
+                    $$ language plprql;
+
+                    create function get_identifier_name_with(suffix text) returns text as $$
+                        from s"SELECT name AS name$suffix FROM base.people WHERE id = 1"
+                        select {name = s"name$suffix || $suffix"}
+                    $$ language plprql;
+                    "#,
+                None,
+                &[],
+            )?;
+
+            assert_eq!(
+                Spi::get_one::<Vec<String>>("select array_agg(name) from get_names_on(1) as name")?,
+                Some(vec![
+                    "Anakin Skywalker".to_string(),
+                    "Beru Whitesun lars".to_string(),
+                    "Biggs Darklighter".to_string()
+                ])
+            );
+
+            // Arguments can have the names of columns, and can be referred to by name and by position alike
+            assert_eq!(
+                Spi::get_one::<Vec<String>>("select array_agg(name) from get_names_on_and_taller(1, 180) as name")?,
+                Some(vec![
+                    "Anakin Skywalker".to_string(),
+                    "Biggs Darklighter".to_string(),
+                    "Cliegg Lars".to_string()
+                ])
+            );
+
+            // Names are bound in s-strings too
+            assert_eq!(
+                Spi::get_one::<Vec<String>>("select array_agg(name) from get_names_of(1, 2) as name")?,
+                Some(vec!["C-3PO".to_string(), "Luke Skywalker".to_string()])
+            );
+
+            // But not in string literals or dollar-quoted strings
+            assert_eq!(
+                Spi::get_one::<String>("select get_name_with('!')")?,
+                Some("Luke Skywalker! $suffix $suffix".to_string())
+            );
+
+            // Nor in quoted identifiers, escape strings with escaped quotes, or identifiers that contain a `$`
+            assert_eq!(
+                Spi::get_one::<String>("select get_quoted_name_with('!')")?,
+                Some("Luke Skywalker!".to_string())
+            );
+            assert_eq!(
+                Spi::get_one::<String>("select get_escaped_name_with('!')")?,
+                Some("Luke Skywalker! '$suffix".to_string())
+            );
+            assert_eq!(
+                Spi::get_one::<String>("select get_identifier_name_with('!')")?,
+                Some("Luke Skywalker!".to_string())
+            );
+
+            // Names that do not refer to an argument are rejected
+            _ = client.update(
+                r#"
+                    do $$
+                    begin
+                        create function get_names_on_planet(planet int) returns setof text as $x$
+                            from base.people
+                            filter planet_id == $planet_id
+                            select {name}
+                        $x$ language plprql;
+                        raise exception 'undefined argument should have been rejected';
+                    exception when others then
+                        if sqlerrm <> 'Function has no argument named "planet_id"' then
+                            raise;
+                        end if;
+                    end $$;
+                    "#,
+                None,
+                &[],
+            )?;
+
+            Ok(())
+        })
+    }
+
+    #[pg_test]
+    fn test_triggers() -> Result<(), pgrx::spi::Error> {
+        Spi::connect_mut(|client| {
+            _ = client.update(
+                r#"
+                    create table orders (id int primary key, price numeric, quantity int, total numeric);
+
+                    create function skip_free_orders() returns trigger as $$
+                        from new
+                        filter price > 0
+                    $$ language plprql;
+
+                    create function set_total() returns trigger as $$
+                        from new
+                        select {id, price, quantity, total = price * quantity}
+                    $$ language plprql;
+
+                    create function keep_price() returns trigger as $$
+                        from new
+                        join old (==id)
+                        filter new.price >= old.price
+                        select {new.id, new.price, new.quantity, new.total}
+                    $$ language plprql;
+
+                    create trigger a_skip_free_orders before insert on orders
+                        for each row execute function skip_free_orders();
+                    create trigger b_set_total before insert or update on orders
+                        for each row execute function set_total();
+                    create trigger c_keep_price before update on orders
+                        for each row execute function keep_price();
+
+                    insert into orders values (1, 10, 2, null), (2, 0, 1, null), (3, 5, 1, null);
+                    "#,
+                None,
+                &[],
+            )?;
+
+            // Rows are stored as the query returns them, and not at all if it returns no rows
+            assert_eq!(
+                Spi::get_one::<String>("select string_agg(format('%s:%s', id, total), ',' order by id) from orders")?,
+                Some("1:20,3:5".to_string())
+            );
+
+            // Both new and old rows are available on update
+            _ = client.update("update orders set price = 4 where id = 1", None, &[])?;
+            _ = client.update("update orders set quantity = 3 where id = 1", None, &[])?;
+            assert_eq!(
+                Spi::get_two::<String, String>("select price::text, total::text from orders where id = 1")?,
+                (Some("10".to_string()), Some("30".to_string()))
+            );
+
+            // Columns are cast to the table's columns, and matched by name if the function says so
+            _ = client.update(
+                r#"
+                    create table items (name text, code int);
+
+                    create function shorten_name() returns trigger as $$
+                        from new
+                        select {code = s"({code} * 10)::bigint", name = s"left({name}, 3)"}
+                    $$ language plprql set plprql.match_columns_by_name = on;
+
+                    create trigger shorten_name before insert on items
+                        for each row execute function shorten_name();
+
+                    insert into items values ('abcdef', 1);
+                    "#,
+                None,
+                &[],
+            )?;
+
+            assert_eq!(
+                Spi::get_two::<String, i32>("select name::text, code from items")?,
+                (Some("abc".to_string()), Some(10))
+            );
+
+            // Statement-level triggers run their query but return null, as PostgreSQL rejects any other result
+            _ = client.update(
+                r#"
+                    create sequence statements;
+
+                    create function count_statement() returns trigger as $$
+                        from s"SELECT nextval('statements') AS n"
+                    $$ language plprql;
+
+                    create trigger count_statement before insert on items
+                        for each statement execute function count_statement();
+
+                    insert into items values ('ghi', 2), ('jkl', 3);
+                    "#,
+                None,
+                &[],
+            )?;
+
+            assert_eq!(Spi::get_one::<i64>("select count(*) from items")?, Some(3));
+            assert_eq!(Spi::get_one::<i64>("select currval('statements')")?, Some(1));
+
+            Ok(())
+        })
+    }
+
+    #[pg_test]
+    fn test_do_blocks() -> Result<(), pgrx::spi::Error> {
+        Spi::connect_mut(|client| {
+            _ = client.update(include_str!("../sql/starwars.sql"), None, &[])?;
+
+            _ = client.update(
+                r#"
+                    do $$
+                        from base.people
+                        filter planet_id == 1
+                        select {name, height}
+                    $$ language plprql;
+                    "#,
+                None,
+                &[],
+            )?;
+
+            // The whole result is run whether or not its first rows are reported
+            _ = client.update("create sequence counter", None, &[])?;
+            for inline_rows in [0, 2, 10] {
+                _ = client.update(&format!("set plprql.inline_rows = {inline_rows}"), None, &[])?;
+                _ = client.update(
+                    r#"do $$ from s"SELECT nextval('counter') AS n FROM generate_series(1, 5)" $$ language plprql"#,
+                    None,
+                    &[],
+                )?;
+            }
+
+            assert_eq!(Spi::get_one::<i64>("select currval('counter')")?, Some(15));
+
+            // PRQL that does not compile is reported as an error
+            _ = client.update(
+                r#"
+                    do $x$
+                    begin
+                        execute 'do $$ from base.people | filter $$ language plprql';
+                        raise exception 'invalid PRQL should have been rejected';
+                    exception when fdw_error then null;
+                    end $x$;
+                    "#,
+                None,
+                &[],
+            )?;
+
+            Ok(())
+        })
+    }
+
+    #[pg_test]
+    fn test_procedures() -> Result<(), pgrx::spi::Error> {
+        Spi::connect_mut(|client| {
+            _ = client.update(include_str!("../sql/starwars.sql"), None, &[])?;
+
+            _ = client.update(
+                r#"
+                    create procedure get_height(person text, inout height int) as $$
+                        from base.people
+                        filter name == $person
+                        select {height}
+                    $$ language plprql;
+
+                    create procedure open_people_on(inout people refcursor, planet int) as $$
+                        from base.people
+                        filter planet_id == $planet
+                        sort name
+                        select {name}
+                    $$ language plprql;
+
+                    create procedure commit_between(inout n bigint) as $$
+                        from s"SELECT 1 AS n"
+
+                        commit
+
+                        from s"SELECT 2 AS n"
+                    $$ language plprql;
+                    "#,
+                None,
+                &[],
+            )?;
+
+            // The first row of the pipeline gives the INOUT parameters, which are null if there is no row
+            assert_eq!(
+                client
+                    .update("call get_height('Luke Skywalker', null)", None, &[])?
+                    .first()
+                    .get_one::<i32>()?,
+                Some(172)
+            );
+            assert_eq!(
+                client
+                    .update("call get_height('Nobody', 1)", None, &[])?
+                    .first()
+                    .get_one::<i32>()?,
+                None
+            );
+
+            // A refcursor parameter names the cursor opened over the pipeline
+            assert_eq!(
+                client
+                    .update("call open_people_on('people_cursor', 1)", None, &[])?
+                    .first()
+                    .get_one::<String>()?,
+                Some("people_cursor".to_string())
+            );
+            assert_eq!(
+                client
+                    .update("fetch 2 from people_cursor", None, &[])?
+                    .map(|row| row.get::<String>(1))
+                    .collect::<Result<Vec<_>, _>>()?,
+                vec![
+                    Some("Anakin Skywalker".to_string()),
+                    Some("Beru Whitesun lars".to_string())
+                ]
+            );
+
+            let cursor = client
+                .update("call open_people_on(null, 1)", None, &[])?
+                .first()
+                .get_one::<String>()?
+                .expect("cursor should have a generated name");
+            assert_eq!(
+                client
+                    .update(&format!("fetch 1 from \"{cursor}\""), None, &[])?
+                    .first()
+                    .get_one::<String>()?,
+                Some("Anakin Skywalker".to_string())
+            );
+
+            // Transaction control is only allowed where CALL is non-atomic, which a test's transaction is not
+            _ = client.update(
+                r#"
+                    do $$
+                    begin
+                        call commit_between(null);
+                        raise exception 'commit should have been rejected';
+                    exception when invalid_transaction_termination then null;
+                    end $$;
+                    "#,
+                None,
+                &[],
+            )?;
+
+            // A line that only says commit is rejected unless blank lines set it apart from the pipelines
+            _ = client.update(
+                r#"
+                    do $do$
+                    begin
+                        create procedure commit_in_pipeline() as $$
+                            from s"SELECT 1 AS n"
+                            commit
+                            from s"SELECT 2 AS n"
+                        $$ language plprql;
+                        raise exception 'commit inside a pipeline should have been rejected';
+                    exception when others then
+                        if sqlerrm <> '"commit" on line 3 is part of a pipeline; separate it from pipelines with blank lines' then
+                            raise;
+                        end if;
+                    end $do$;
+                    "#,
+                None,
+                &[],
+            )?;
+
+            Ok(())
+        })
+    }
+
+    // Drops the schema of a test that runs outside of a test's transaction when the test ends, whether it passes or
+    // not, so that its objects are not left behind
+    struct DropSchema(&'static str);
+
+    impl Drop for DropSchema {
+        fn drop(&mut self) {
+            if let Ok((mut client, _)) = pgrx_tests::client() {
+                _ = client.batch_execute(&format!("drop schema if exists {} cascade", self.0));
+            }
+        }
+    }
+
+    // CALL is only non-atomic outside of a transaction block, so this runs from a client of its own rather than in a
+    // test's transaction, in a schema of its own. Running a test function starts the test framework.
+    #[test]
+    fn test_non_atomic_procedures() {
+        pgrx_tests::run_test("test_pgrx_tests", None, crate::pg_test::postgresql_conf_options())
+            .expect("test framework should start");
+        let _drop_schema = DropSchema("non_atomic_procedures");
+
+        let (mut client, _) = pgrx_tests::client().expect("client should connect");
+        client
+            .batch_execute(
+                r#"
+                    drop schema if exists non_atomic_procedures cascade;
+                    create schema non_atomic_procedures;
+                    set search_path = non_atomic_procedures;
+
+                    create table calls (n int);
+
+                    create function record_call(n int) returns int as $$
+                        insert into calls values (n) returning n
+                    $$ language sql;
+
+                    create procedure commit_then_rollback() as $$
+                        from s"SELECT record_call(n) AS n FROM generate_series(1, 2) AS n"
+
+                        commit
+
+                        from s"SELECT record_call(3) AS n"
+
+                        rollback
+
+                        from s"SELECT record_call(4) AS n"
+                    $$ language plprql;
+                    "#,
+            )
+            .expect("procedure should be created");
+
+        // Every row of an earlier pipeline is run, work before a commit survives a later rollback, and work after the
+        // last step is committed with the call
+        client
+            .simple_query("call commit_then_rollback()")
+            .expect("procedure should be called");
+
+        let calls = client
+            .query_one("select array_agg(n order by n) from calls", &[])
+            .expect("calls should be recorded")
+            .get::<_, Vec<i32>>(0);
+        assert_eq!(calls, vec![1, 2, 4]);
+    }
+
+    #[pg_test]
+    fn test_return_refcursor() -> Result<(), pgrx::spi::Error> {
+        Spi::connect_mut(|client| {
+            _ = client.update(include_str!("../sql/starwars.sql"), None, &[])?;
+
+            _ = client.update(
+                r#"
+                    create function people_on(planet int) returns refcursor as $$
+                        from base.people
+                        filter planet_id == $planet
+                        sort name
+                        select {name, height}
+                    $$ language plprql;
+
+                    create function named_people_on(people refcursor, planet int) returns refcursor as $$
+                        from base.people
+                        filter planet_id == $planet
+                        sort name
+                        select {name}
+                    $$ language plprql;
+                    "#,
+                None,
+                &[],
+            )?;
+
+            // A cursor without a refcursor argument gets a generated name
+            let cursor = client
+                .update("select people_on(1)", None, &[])?
+                .first()
+                .get_one::<String>()?
+                .expect("cursor should have a generated name");
+            assert_eq!(
+                client
+                    .update(&format!("fetch 2 from \"{cursor}\""), None, &[])?
+                    .map(|row| Ok((row.get::<String>(1)?, row.get::<i32>(2)?)))
+                    .collect::<Result<Vec<_>, pgrx::spi::Error>>()?,
+                vec![
+                    (Some("Anakin Skywalker".to_string()), Some(188)),
+                    (Some("Beru Whitesun lars".to_string()), Some(165))
+                ]
+            );
+            assert_eq!(
+                client
+                    .update(&format!("fetch 1 from \"{cursor}\""), None, &[])?
+                    .first()
+                    .get_one::<String>()?,
+                Some("Biggs Darklighter".to_string())
+            );
+
+            // A refcursor argument names the cursor
+            assert_eq!(
+                client
+                    .update("select named_people_on('people_cursor', 1)", None, &[])?
+                    .first()
+                    .get_one::<String>()?,
+                Some("people_cursor".to_string())
+            );
+            assert_eq!(
+                client
+                    .update("fetch all from people_cursor", None, &[])?
+                    .map(|row| row.get::<String>(1))
+                    .collect::<Result<Vec<_>, _>>()?
+                    .len(),
+                10
+            );
+
+            // Named cursors are opened from the saved plan like any other, so it is only prepared once
+            _ = client.update("select named_people_on('other_people_cursor', 2)", None, &[])?;
+            assert_eq!(
+                Spi::get_two::<i64, i64>(
+                    "select misses, prepares from plprql_cache_stats() where function_oid = 'named_people_on'::regproc",
+                )?,
+                (Some(1), Some(1))
+            );
+
+            Ok(())
+        })
+    }
+
     #[pg_test]
     fn test_readme_examples() -> Result<(), pgrx::spi::Error> {
         Spi::connect_mut(|client| {
@@ -16,15 +16,123 @@
 //
 // Modifications:
 // - Renamed Cell to AnyDatum
+// - Added Raw variant for types without a dedicated variant
+// - Added Composite variant for composite and record types
+// - Replaced per-type array variants with an Array variant for any element type and number of dimensions
+// - Added Range and Multirange variants for built-in and user-defined range types
+// - Added Enum variant for enum types and resolved domains to their base types
+// - Added variants for binary, network, bit string and text-like types, money, timetz and oid
 
+use pgrx::varlena::varsize;
 use pgrx::{
-    PgBuiltInOids, PgOid,
-    datum::{AnyNumeric, Date, FromDatum, Interval, IntoDatum, JsonB, Time, Timestamp, TimestampWithTimeZone, Uuid},
+    PgBuiltInOids, PgOid, PgTupleDesc,
+    datum::{
+        AnyNumeric, Date, FromDatum, Inet, Interval, IntoDatum, JsonB, Time, TimeWithTimeZone, Timestamp,
+        TimestampWithTimeZone, Uuid,
+    },
     fcinfo, pg_sys,
 };
-use std::ffi::CStr;
+use std::ffi::{CStr, CString, c_char, c_int};
 use std::fmt;
 
+unsafe extern "C-unwind" {
+    // Declared in utils/datum.h which pgrx does not generate bindings for
+    fn datumGetSize(value: pg_sys::Datum, typByVal: bool, typLen: c_int) -> usize;
+    fn datumCopy(value: pg_sys::Datum, typByVal: bool, typLen: c_int) -> pg_sys::Datum;
+}
+
+#[cfg(not(feature = "pg13"))]
+unsafe extern "C-unwind" {
+    // Declared in utils/multirangetypes.h which pgrx does not generate bindings for
+    fn multirange_deserialize(
+        rangetyp: *mut pg_sys::TypeCacheEntry,
+        multirange: *const pg_sys::varlena,
+        range_count: *mut i32,
+        ranges: *mut *mut *mut pg_sys::RangeType,
+    );
+    fn make_multirange(
+        mltrngtypoid: pg_sys::Oid,
+        rangetyp: *mut pg_sys::TypeCacheEntry,
+        range_count: i32,
+        ranges: *mut *mut pg_sys::RangeType,
+    ) -> *mut pg_sys::varlena;
+}
+
+// A value of a type without a dedicated variant. Pass-by-reference values are copied out of the memory context they
+// were read in, so the value stays valid after e.g. SPI has freed its memory, and are copied back into the current
+// memory context with datumCopy when converted into a datum again.
+#[derive(Debug, Clone)]
+pub struct RawDatum {
+    type_oid: pg_sys::Oid,
+    typlen: i16,
+    typbyval: bool,
+    datum: pg_sys::Datum,
+    bytes: Vec<u8>,
+}
+
+impl RawDatum {
+    unsafe fn from_datum(datum: pg_sys::Datum, type_oid: pg_sys::Oid) -> Self {
+        let mut typlen = 0;
+        let mut typbyval = false;
+        unsafe { pg_sys::get_typlenbyval(type_oid, &mut typlen, &mut typbyval) };
+
+        if typbyval {
+            return RawDatum {
+                type_oid,
+                typlen,
+                typbyval,
+                datum,
+                bytes: vec![],
+            };
+        }
+
+        unsafe {
+            // Toasted and expanded values are flattened, so the copy does not point into other memory
+            let datum = match typlen {
+                -1 => pg_sys::Datum::from(pg_sys::pg_detoast_datum_packed(datum.cast_mut_ptr())),
+                _ => datum,
+            };
+
+            let size = pg_sys::ffi::pg_guard_ffi_boundary(|| datumGetSize(datum, typbyval, typlen as c_int));
+            let bytes = std::slice::from_raw_parts(datum.cast_mut_ptr::<u8>(), size).to_vec();
+
+            RawDatum {
+                type_oid,
+                typlen,
+                typbyval,
+                datum: pg_sys::Datum::from(0),
+                bytes,
+            }
+        }
+    }
+}
+
+impl IntoDatum for RawDatum {
+    fn into_datum(self) -> Option<pg_sys::Datum> {
+        if self.typbyval {
+            return Some(self.datum);
+        }
+
+        let datum = pg_sys::Datum::from(self.bytes.as_ptr());
+        Some(unsafe { pg_sys::ffi::pg_guard_ffi_boundary(|| datumCopy(datum, false, self.typlen as c_int)) })
+    }
+
+    fn type_oid() -> pg_sys::Oid {
+        pg_sys::Oid::INVALID
+    }
+}
+
+// Render the text produced by the type's output function as a literal
+impl fmt::Display for RawDatum {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let datum = self
+            .clone()
+            .into_datum()
+            .expect("raw datum should convert into a datum");
+        write!(f, "'{}'", unsafe { type_output(datum, self.type_oid) })
+    }
+}
+
 #[derive(Debug)]
 pub enum AnyDatum {
     Bool(bool),
@@ -43,30 +151,492 @@ pub enum AnyDatum {
     Interval(Interval),
     Json(JsonB),
     Uuid(Uuid),
-    BoolArray(Vec<Option<bool>>),
-    I16Array(Vec<Option<i16>>),
-    I32Array(Vec<Option<i32>>),
-    I64Array(Vec<Option<i64>>),
-    F32Array(Vec<Option<f32>>),
-    F64Array(Vec<Option<f64>>),
-    StringArray(Vec<Option<String>>),
+    Bytea(Vec<u8>),
+    Inet(Inet),
+    Cidr(String),
+    MacAddr(String),
+    Bit(String),
+    VarBit(String),
+    Bpchar(String),
+    Name(String),
+    JsonText(String),
+    Xml(String),
+    Money(i64),
+    Timetz(TimeWithTimeZone),
+    Oid(pg_sys::Oid),
+    Enum(EnumDatum),
+    Array(ArrayDatum),
+    Range(RangeDatum),
+    #[cfg(not(feature = "pg13"))]
+    Multirange(MultirangeDatum),
+    Composite(CompositeDatum),
+    Raw(RawDatum),
 }
 
 impl AnyDatum {
     /// Check if datum is an array type
     #[allow(dead_code)]
     pub fn is_array(&self) -> bool {
-        matches!(
-            self,
-            AnyDatum::BoolArray(_)
-                | AnyDatum::I16Array(_)
-                | AnyDatum::I32Array(_)
-                | AnyDatum::I64Array(_)
-                | AnyDatum::F32Array(_)
-                | AnyDatum::F64Array(_)
-                | AnyDatum::StringArray(_)
-        )
+        matches!(self, AnyDatum::Array(_))
+    }
+}
+
+// A value of an enum type, i.e. the OID of one of the type's labels in pg_enum
+#[derive(Debug, Clone, Copy)]
+pub struct EnumDatum {
+    label_oid: pg_sys::Oid,
+}
+
+impl IntoDatum for EnumDatum {
+    fn into_datum(self) -> Option<pg_sys::Datum> {
+        self.label_oid.into_datum()
     }
+
+    fn type_oid() -> pg_sys::Oid {
+        pg_sys::ANYENUMOID
+    }
+}
+
+impl fmt::Display for EnumDatum {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        unsafe {
+            let label = fcinfo::direct_function_call_as_datum(pg_sys::enum_out, &[self.label_oid.into_datum()])
+                .expect("datum should be a valid enum label");
+            let label_cstr = CStr::from_ptr(label.cast_mut_ptr());
+            write!(
+                f,
+                "'{}'",
+                label_cstr.to_str().expect("enum label should be a valid string")
+            )
+        }
+    }
+}
+
+// A value of a composite or record type. Fields are converted recursively, so composites can be nested. Dropped
+// attributes are kept as nameless nulls, so the value can be formed into a tuple of the row type again.
+#[derive(Debug, Clone)]
+pub struct CompositeDatum {
+    type_oid: pg_sys::Oid,
+    typmod: i32,
+    names: Vec<Option<String>>,
+    fields: Vec<Option<AnyDatum>>,
+}
+
+impl CompositeDatum {
+    unsafe fn from_datum(datum: pg_sys::Datum) -> Self {
+        unsafe {
+            let header = pg_sys::pg_detoast_datum(datum.cast_mut_ptr()) as pg_sys::HeapTupleHeader;
+            let type_oid = (*header).t_choice.t_datum.datum_typeid;
+            let typmod = (*header).t_choice.t_datum.datum_typmod;
+            let tupdesc = PgTupleDesc::from_pg(pg_sys::lookup_rowtype_tupdesc(type_oid, typmod));
+
+            let mut tuple = pg_sys::HeapTupleData {
+                t_len: varsize(header as *const pg_sys::varlena) as u32,
+                t_self: pg_sys::ItemPointerData::default(),
+                t_tableOid: pg_sys::InvalidOid,
+                t_data: header,
+            };
+
+            let mut datums = vec![pg_sys::Datum::from(0); tupdesc.len()];
+            let mut nulls = vec![true; tupdesc.len()];
+            pg_sys::heap_deform_tuple(&mut tuple, tupdesc.as_ptr(), datums.as_mut_ptr(), nulls.as_mut_ptr());
+
+            let (names, fields) = tupdesc
+                .iter()
+                .zip(datums.into_iter().zip(nulls))
+                .map(|(attribute, (datum, is_null))| match attribute.is_dropped() {
+                    true => (None, None),
+                    false => (
+                        Some(attribute.name().to_string()),
+                        AnyDatum::from_polymorphic_datum(datum, is_null, attribute.type_oid().value()),
+                    ),
+                })
+                .unzip();
+
+            CompositeDatum {
+                type_oid,
+                typmod,
+                names,
+                fields,
+            }
+        }
+    }
+}
+
+impl IntoDatum for CompositeDatum {
+    fn into_datum(self) -> Option<pg_sys::Datum> {
+        unsafe {
+            let tupdesc = PgTupleDesc::from_pg(pg_sys::lookup_rowtype_tupdesc(self.type_oid, self.typmod));
+            let (mut datums, mut nulls): (Vec<_>, Vec<_>) = self
+                .fields
+                .into_iter()
+                .map(|field| match field.and_then(|value| value.into_datum()) {
+                    Some(datum) => (datum, false),
+                    None => (pg_sys::Datum::from(0), true),
+                })
+                .unzip();
+
+            let tuple = pg_sys::heap_form_tuple(tupdesc.as_ptr(), datums.as_mut_ptr(), nulls.as_mut_ptr());
+            Some(pg_sys::HeapTupleHeaderGetDatum((*tuple).t_data))
+        }
+    }
+
+    fn type_oid() -> pg_sys::Oid {
+        pg_sys::RECORDOID
+    }
+}
+
+// Render as a row constructor, cast to the row type unless it is an anonymous record
+impl fmt::Display for CompositeDatum {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fields = self
+            .names
+            .iter()
+            .zip(&self.fields)
+            .filter(|(name, _)| name.is_some())
+            .map(|(_, field)| match field {
+                Some(value) => format!("{value}"),
+                None => "null".to_owned(),
+            })
+            .collect::<Vec<String>>()
+            .join(", ");
+
+        match self.type_oid == pg_sys::RECORDOID {
+            true => write!(f, "ROW({fields})"),
+            false => {
+                let type_name = unsafe { CStr::from_ptr(pg_sys::format_type_be(self.type_oid)) };
+                write!(
+                    f,
+                    "ROW({fields})::{}",
+                    type_name.to_str().expect("type name should be a valid string")
+                )
+            }
+        }
+    }
+}
+
+// A value of an array type with any element type and any number of dimensions. Elements are converted like any
+// other value and kept in row-major order along with the array's dimensions and lower bounds.
+#[derive(Debug, Clone)]
+pub struct ArrayDatum {
+    element_type: pg_sys::Oid,
+    dims: Vec<i32>,
+    lower_bounds: Vec<i32>,
+    elements: Vec<Option<AnyDatum>>,
+}
+
+impl ArrayDatum {
+    unsafe fn from_datum(datum: pg_sys::Datum) -> Self {
+        unsafe {
+            let array = pg_sys::pg_detoast_datum(datum.cast_mut_ptr()) as *mut pg_sys::ArrayType;
+            let element_type = (*array).elemtype;
+            let ndim = (*array).ndim as usize;
+
+            // Dimensions and lower bounds follow the array header
+            let dims = array.add(1) as *const i32;
+            let lower_bounds = dims.add(ndim);
+
+            let (typlen, typbyval, typalign) = type_storage(element_type);
+            let mut element_datums = std::ptr::null_mut();
+            let mut element_nulls = std::ptr::null_mut();
+            let mut len = 0;
+            pg_sys::deconstruct_array(
+                array,
+                element_type,
+                typlen as c_int,
+                typbyval,
+                typalign,
+                &mut element_datums,
+                &mut element_nulls,
+                &mut len,
+            );
+
+            let elements = match len {
+                0 => vec![],
+                _ => std::slice::from_raw_parts(element_datums, len as usize)
+                    .iter()
+                    .zip(std::slice::from_raw_parts(element_nulls, len as usize))
+                    .map(|(datum, is_null)| AnyDatum::from_polymorphic_datum(*datum, *is_null, element_type))
+                    .collect(),
+            };
+
+            ArrayDatum {
+                element_type,
+                dims: std::slice::from_raw_parts(dims, ndim).to_vec(),
+                lower_bounds: std::slice::from_raw_parts(lower_bounds, ndim).to_vec(),
+                elements,
+            }
+        }
+    }
+}
+
+impl IntoDatum for ArrayDatum {
+    fn into_datum(mut self) -> Option<pg_sys::Datum> {
+        let (mut datums, mut nulls): (Vec<_>, Vec<_>) = self
+            .elements
+            .into_iter()
+            .map(|element| match element.and_then(|value| value.into_datum()) {
+                Some(datum) => (datum, false),
+                None => (pg_sys::Datum::from(0), true),
+            })
+            .unzip();
+
+        unsafe {
+            let (typlen, typbyval, typalign) = type_storage(self.element_type);
+            let array = pg_sys::construct_md_array(
+                datums.as_mut_ptr(),
+                nulls.as_mut_ptr(),
+                self.dims.len() as c_int,
+                self.dims.as_mut_ptr(),
+                self.lower_bounds.as_mut_ptr(),
+                self.element_type,
+                typlen as c_int,
+                typbyval,
+                typalign,
+            );
+            Some(pg_sys::Datum::from(array))
+        }
+    }
+
+    fn type_oid() -> pg_sys::Oid {
+        pg_sys::ANYARRAYOID
+    }
+}
+
+// Render lower bounds the way PostgreSQL does when they differ from the default of 1
+impl fmt::Display for ArrayDatum {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.lower_bounds.iter().any(|lower_bound| *lower_bound != 1) {
+            for (dim, lower_bound) in self.dims.iter().zip(&self.lower_bounds) {
+                write!(f, "[{}:{}]", lower_bound, lower_bound + dim - 1)?;
+            }
+            write!(f, "=")?;
+        }
+
+        write_array(&self.elements, &self.dims, f)
+    }
+}
+
+// A bound of a range. Infinite bounds have no value.
+#[derive(Debug, Clone)]
+pub struct RangeBoundDatum {
+    value: Option<Box<AnyDatum>>,
+    inclusive: bool,
+}
+
+impl RangeBoundDatum {
+    unsafe fn from_range_bound(bound: pg_sys::RangeBound, element_type: pg_sys::Oid) -> Self {
+        let value = match bound.infinite {
+            true => None,
+            false => unsafe { AnyDatum::from_polymorphic_datum(bound.val, false, element_type) }.map(Box::new),
+        };
+
+        RangeBoundDatum {
+            value,
+            inclusive: bound.inclusive,
+        }
+    }
+
+    fn into_range_bound(self, lower: bool) -> pg_sys::RangeBound {
+        let value = self.value.and_then(|value| value.into_datum());
+        pg_sys::RangeBound {
+            infinite: value.is_none(),
+            val: value.unwrap_or_else(|| pg_sys::Datum::from(0)),
+            inclusive: self.inclusive,
+            lower,
+        }
+    }
+}
+
+// A value of a range type. Bound values are converted like any other value of the range's element type. Empty ranges
+// have no bounds, as the bounds PostgreSQL deserializes them with hold no values.
+#[derive(Debug, Clone)]
+pub struct RangeDatum {
+    type_oid: pg_sys::Oid,
+    bounds: Option<(RangeBoundDatum, RangeBoundDatum)>,
+}
+
+impl RangeDatum {
+    unsafe fn from_datum(datum: pg_sys::Datum) -> Self {
+        unsafe {
+            let range = pg_sys::pg_detoast_datum(datum.cast_mut_ptr()) as *mut pg_sys::RangeType;
+            let type_oid = (*range).rangetypid;
+            let typcache = pg_sys::lookup_type_cache(type_oid, pg_sys::TYPECACHE_RANGE_INFO as c_int);
+            let element_type = (*(*typcache).rngelemtype).type_id;
+
+            let mut lower = pg_sys::RangeBound::default();
+            let mut upper = pg_sys::RangeBound::default();
+            let mut empty = false;
+            pg_sys::range_deserialize(typcache, range, &mut lower, &mut upper, &mut empty);
+
+            let bounds = (!empty).then(|| {
+                (
+                    RangeBoundDatum::from_range_bound(lower, element_type),
+                    RangeBoundDatum::from_range_bound(upper, element_type),
+                )
+            });
+
+            RangeDatum { type_oid, bounds }
+        }
+    }
+
+    fn into_range(self) -> *mut pg_sys::RangeType {
+        let empty = self.bounds.is_none();
+        let (mut lower, mut upper) = match self.bounds {
+            Some((lower, upper)) => (lower.into_range_bound(true), upper.into_range_bound(false)),
+            None => (
+                pg_sys::RangeBound {
+                    lower: true,
+                    ..Default::default()
+                },
+                pg_sys::RangeBound::default(),
+            ),
+        };
+
+        unsafe {
+            let typcache = pg_sys::lookup_type_cache(self.type_oid, pg_sys::TYPECACHE_RANGE_INFO as c_int);
+
+            #[cfg(any(feature = "pg13", feature = "pg14", feature = "pg15"))]
+            let range = pg_sys::make_range(typcache, &mut lower, &mut upper, empty);
+
+            #[cfg(not(any(feature = "pg13", feature = "pg14", feature = "pg15")))]
+            let range = pg_sys::make_range(typcache, &mut lower, &mut upper, empty, std::ptr::null_mut());
+
+            range
+        }
+    }
+}
+
+impl IntoDatum for RangeDatum {
+    fn into_datum(self) -> Option<pg_sys::Datum> {
+        Some(pg_sys::Datum::from(self.into_range()))
+    }
+
+    fn type_oid() -> pg_sys::Oid {
+        pg_sys::ANYRANGEOID
+    }
+}
+
+// Render the way PostgreSQL does, leaving out the values of infinite bounds
+impl fmt::Display for RangeDatum {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Some((lower, upper)) = &self.bounds else {
+            return write!(f, "empty");
+        };
+
+        write!(f, "{}", if lower.inclusive { "[" } else { "(" })?;
+        if let Some(value) = &lower.value {
+            write!(f, "{value}")?;
+        }
+        write!(f, ",")?;
+        if let Some(value) = &upper.value {
+            write!(f, "{value}")?;
+        }
+        write!(f, "{}", if upper.inclusive { "]" } else { ")" })
+    }
+}
+
+// A value of a multirange type, kept as the ranges it consists of
+#[cfg(not(feature = "pg13"))]
+#[derive(Debug, Clone)]
+pub struct MultirangeDatum {
+    type_oid: pg_sys::Oid,
+    ranges: Vec<RangeDatum>,
+}
+
+#[cfg(not(feature = "pg13"))]
+impl MultirangeDatum {
+    unsafe fn from_datum(datum: pg_sys::Datum, type_oid: pg_sys::Oid) -> Self {
+        unsafe {
+            let multirange = pg_sys::pg_detoast_datum(datum.cast_mut_ptr());
+            let typcache = pg_sys::lookup_type_cache(type_oid, pg_sys::TYPECACHE_MULTIRANGE_INFO as c_int);
+
+            let mut range_count = 0;
+            let mut ranges = std::ptr::null_mut();
+            pg_sys::ffi::pg_guard_ffi_boundary(|| {
+                multirange_deserialize((*typcache).rngtype, multirange, &mut range_count, &mut ranges)
+            });
+
+            let ranges = match range_count {
+                0 => vec![],
+                _ => std::slice::from_raw_parts(ranges, range_count as usize)
+                    .iter()
+                    .map(|range| RangeDatum::from_datum(pg_sys::Datum::from(*range)))
+                    .collect(),
+            };
+
+            MultirangeDatum { type_oid, ranges }
+        }
+    }
+}
+
+#[cfg(not(feature = "pg13"))]
+impl IntoDatum for MultirangeDatum {
+    fn into_datum(self) -> Option<pg_sys::Datum> {
+        let mut ranges = self.ranges.into_iter().map(RangeDatum::into_range).collect::<Vec<_>>();
+
+        unsafe {
+            let typcache = pg_sys::lookup_type_cache(self.type_oid, pg_sys::TYPECACHE_MULTIRANGE_INFO as c_int);
+            let multirange = pg_sys::ffi::pg_guard_ffi_boundary(|| {
+                make_multirange(
+                    self.type_oid,
+                    (*typcache).rngtype,
+                    ranges.len() as i32,
+                    ranges.as_mut_ptr(),
+                )
+            });
+            Some(pg_sys::Datum::from(multirange))
+        }
+    }
+
+    fn type_oid() -> pg_sys::Oid {
+        pg_sys::ANYMULTIRANGEOID
+    }
+}
+
+#[cfg(not(feature = "pg13"))]
+impl fmt::Display for MultirangeDatum {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ranges = self
+            .ranges
+            .iter()
+            .map(|range| format!("{range}"))
+            .collect::<Vec<String>>()
+            .join(",");
+        write!(f, "{{{ranges}}}")
+    }
+}
+
+// Text representation of a value, as produced by its type's output function
+unsafe fn type_output(datum: pg_sys::Datum, type_oid: pg_sys::Oid) -> String {
+    unsafe {
+        let mut output_function = pg_sys::InvalidOid;
+        let mut is_varlena = false;
+        pg_sys::getTypeOutputInfo(type_oid, &mut output_function, &mut is_varlena);
+        let output = CStr::from_ptr(pg_sys::OidOutputFunctionCall(output_function, datum));
+        output.to_str().expect("output should be a valid string").to_owned()
+    }
+}
+
+// Value of a type read from its text representation by the type's input function
+fn type_input(text: &str, type_oid: pg_sys::Oid) -> pg_sys::Datum {
+    let text = CString::new(text).expect("text representation contained a null byte");
+
+    unsafe {
+        let mut input_function = pg_sys::InvalidOid;
+        let mut io_param = pg_sys::InvalidOid;
+        pg_sys::getTypeInputInfo(type_oid, &mut input_function, &mut io_param);
+        pg_sys::OidInputFunctionCall(input_function, text.as_ptr().cast_mut(), io_param, -1)
+    }
+}
+
+// Length, pass-by-value and alignment of a type, needed to read and write its values in arrays
+fn type_storage(type_oid: pg_sys::Oid) -> (i16, bool, c_char) {
+    let mut typlen = 0;
+    let mut typbyval = false;
+    let mut typalign = 0;
+    unsafe { pg_sys::get_typlenbyvalalign(type_oid, &mut typlen, &mut typbyval, &mut typalign) };
+    (typlen, typbyval, typalign)
 }
 
 unsafe impl Send for AnyDatum {}
@@ -90,13 +660,26 @@ impl Clone for AnyDatum {
             AnyDatum::Interval(v) => AnyDatum::Interval(*v),
             AnyDatum::Json(v) => AnyDatum::Json(JsonB(v.0.clone())),
             AnyDatum::Uuid(v) => AnyDatum::Uuid(*v),
-            AnyDatum::BoolArray(v) => AnyDatum::BoolArray(v.clone()),
-            AnyDatum::I16Array(v) => AnyDatum::I16Array(v.clone()),
-            AnyDatum::I32Array(v) => AnyDatum::I32Array(v.clone()),
-            AnyDatum::I64Array(v) => AnyDatum::I64Array(v.clone()),
-            AnyDatum::F32Array(v) => AnyDatum::F32Array(v.clone()),
-            AnyDatum::F64Array(v) => AnyDatum::F64Array(v.clone()),
-            AnyDatum::StringArray(v) => AnyDatum::StringArray(v.clone()),
+            AnyDatum::Bytea(v) => AnyDatum::Bytea(v.clone()),
+            AnyDatum::Inet(v) => AnyDatum::Inet(Inet(v.0.clone())),
+            AnyDatum::Cidr(v) => AnyDatum::Cidr(v.clone()),
+            AnyDatum::MacAddr(v) => AnyDatum::MacAddr(v.clone()),
+            AnyDatum::Bit(v) => AnyDatum::Bit(v.clone()),
+            AnyDatum::VarBit(v) => AnyDatum::VarBit(v.clone()),
+            AnyDatum::Bpchar(v) => AnyDatum::Bpchar(v.clone()),
+            AnyDatum::Name(v) => AnyDatum::Name(v.clone()),
+            AnyDatum::JsonText(v) => AnyDatum::JsonText(v.clone()),
+            AnyDatum::Xml(v) => AnyDatum::Xml(v.clone()),
+            AnyDatum::Money(v) => AnyDatum::Money(*v),
+            AnyDatum::Timetz(v) => AnyDatum::Timetz(*v),
+            AnyDatum::Oid(v) => AnyDatum::Oid(*v),
+            AnyDatum::Enum(v) => AnyDatum::Enum(*v),
+            AnyDatum::Array(v) => AnyDatum::Array(v.clone()),
+            AnyDatum::Range(v) => AnyDatum::Range(v.clone()),
+            #[cfg(not(feature = "pg13"))]
+            AnyDatum::Multirange(v) => AnyDatum::Multirange(v.clone()),
+            AnyDatum::Composite(v) => AnyDatum::Composite(v.clone()),
+            AnyDatum::Raw(v) => AnyDatum::Raw(v.clone()),
         }
     }
 }
@@ -120,13 +703,28 @@ impl IntoDatum for AnyDatum {
             AnyDatum::Interval(v) => v.into_datum(),
             AnyDatum::Json(v) => v.into_datum(),
             AnyDatum::Uuid(v) => v.into_datum(),
-            AnyDatum::BoolArray(v) => v.into_datum(),
-            AnyDatum::I16Array(v) => v.into_datum(),
-            AnyDatum::I32Array(v) => v.into_datum(),
-            AnyDatum::I64Array(v) => v.into_datum(),
-            AnyDatum::F32Array(v) => v.into_datum(),
-            AnyDatum::F64Array(v) => v.into_datum(),
-            AnyDatum::StringArray(v) => v.into_datum(),
+            AnyDatum::Bytea(v) => v.into_datum(),
+            AnyDatum::Inet(v) => v.into_datum(),
+            AnyDatum::Cidr(v) => Some(type_input(&v, pg_sys::CIDROID)),
+            AnyDatum::MacAddr(v) => Some(type_input(&v, pg_sys::MACADDROID)),
+            AnyDatum::Bit(v) => Some(type_input(&v, pg_sys::BITOID)),
+            AnyDatum::VarBit(v) => Some(type_input(&v, pg_sys::VARBITOID)),
+            // bpchar, json and xml values are stored like text
+            AnyDatum::Bpchar(v) => v.into_datum(),
+            AnyDatum::Name(v) => Some(type_input(&v, pg_sys::NAMEOID)),
+            AnyDatum::JsonText(v) => v.into_datum(),
+            AnyDatum::Xml(v) => v.into_datum(),
+            // money is stored as a 64-bit integer of the currency's smallest unit
+            AnyDatum::Money(v) => v.into_datum(),
+            AnyDatum::Timetz(v) => v.into_datum(),
+            AnyDatum::Oid(v) => v.into_datum(),
+            AnyDatum::Enum(v) => v.into_datum(),
+            AnyDatum::Array(v) => v.into_datum(),
+            AnyDatum::Range(v) => v.into_datum(),
+            #[cfg(not(feature = "pg13"))]
+            AnyDatum::Multirange(v) => v.into_datum(),
+            AnyDatum::Composite(v) => v.into_datum(),
+            AnyDatum::Raw(v) => v.into_datum(),
         }
     }
 
@@ -134,32 +732,9 @@ impl IntoDatum for AnyDatum {
         pg_sys::Oid::INVALID
     }
 
+    // Types without a dedicated variant are kept as raw datums
     fn is_compatible_with(other: pg_sys::Oid) -> bool {
-        Self::type_oid() == other
-            || other == pg_sys::BOOLOID
-            || other == pg_sys::CHAROID
-            || other == pg_sys::INT2OID
-            || other == pg_sys::FLOAT4OID
-            || other == pg_sys::INT4OID
-            || other == pg_sys::FLOAT8OID
-            || other == pg_sys::INT8OID
-            || other == pg_sys::NUMERICOID
-            || other == pg_sys::TEXTOID
-            || other == pg_sys::DATEOID
-            || other == pg_sys::TIMEOID
-            || other == pg_sys::TIMESTAMPOID
-            || other == pg_sys::TIMESTAMPTZOID
-            || other == pg_sys::INTERVALOID
-            || other == pg_sys::JSONBOID
-            || other == pg_sys::UUIDOID
-            || other == pg_sys::BOOLARRAYOID
-            || other == pg_sys::INT2ARRAYOID
-            || other == pg_sys::INT4ARRAYOID
-            || other == pg_sys::INT8ARRAYOID
-            || other == pg_sys::FLOAT4ARRAYOID
-            || other == pg_sys::FLOAT8ARRAYOID
-            || other == pg_sys::TEXTARRAYOID
-            || other == pg_sys::VARCHAROID
+        other != pg_sys::Oid::INVALID
     }
 }
 
@@ -196,45 +771,91 @@ impl FromDatum for AnyDatum {
             },
             PgOid::BuiltIn(PgBuiltInOids::JSONBOID) => unsafe { JsonB::from_datum(datum, is_null).map(AnyDatum::Json) },
             PgOid::BuiltIn(PgBuiltInOids::UUIDOID) => unsafe { Uuid::from_datum(datum, is_null).map(AnyDatum::Uuid) },
-            PgOid::BuiltIn(PgBuiltInOids::BOOLARRAYOID) => unsafe {
-                Vec::<Option<bool>>::from_datum(datum, false).map(AnyDatum::BoolArray)
-            },
-            PgOid::BuiltIn(PgBuiltInOids::INT2ARRAYOID) => unsafe {
-                Vec::<Option<i16>>::from_datum(datum, false).map(AnyDatum::I16Array)
+            PgOid::BuiltIn(PgBuiltInOids::VARCHAROID) => unsafe {
+                String::from_datum(datum, is_null).map(AnyDatum::String)
             },
-            PgOid::BuiltIn(PgBuiltInOids::INT4ARRAYOID) => unsafe {
-                Vec::<Option<i32>>::from_datum(datum, false).map(AnyDatum::I32Array)
+            PgOid::BuiltIn(PgBuiltInOids::BYTEAOID) => unsafe {
+                Vec::<u8>::from_datum(datum, is_null).map(AnyDatum::Bytea)
             },
-            PgOid::BuiltIn(PgBuiltInOids::INT8ARRAYOID) => unsafe {
-                Vec::<Option<i64>>::from_datum(datum, false).map(AnyDatum::I64Array)
+            PgOid::BuiltIn(PgBuiltInOids::INETOID) => unsafe { Inet::from_datum(datum, is_null).map(AnyDatum::Inet) },
+            PgOid::BuiltIn(PgBuiltInOids::CIDROID) => {
+                (!is_null).then(|| AnyDatum::Cidr(unsafe { type_output(datum, typoid) }))
+            }
+            PgOid::BuiltIn(PgBuiltInOids::MACADDROID) => {
+                (!is_null).then(|| AnyDatum::MacAddr(unsafe { type_output(datum, typoid) }))
+            }
+            PgOid::BuiltIn(PgBuiltInOids::BITOID) => {
+                (!is_null).then(|| AnyDatum::Bit(unsafe { type_output(datum, typoid) }))
+            }
+            PgOid::BuiltIn(PgBuiltInOids::VARBITOID) => {
+                (!is_null).then(|| AnyDatum::VarBit(unsafe { type_output(datum, typoid) }))
+            }
+            PgOid::BuiltIn(PgBuiltInOids::BPCHAROID) => unsafe {
+                String::from_datum(datum, is_null).map(AnyDatum::Bpchar)
             },
-            PgOid::BuiltIn(PgBuiltInOids::FLOAT4ARRAYOID) => unsafe {
-                Vec::<Option<f32>>::from_datum(datum, false).map(AnyDatum::F32Array)
+            PgOid::BuiltIn(PgBuiltInOids::NAMEOID) => {
+                (!is_null).then(|| AnyDatum::Name(unsafe { type_output(datum, typoid) }))
+            }
+            PgOid::BuiltIn(PgBuiltInOids::JSONOID) => unsafe {
+                String::from_datum(datum, is_null).map(AnyDatum::JsonText)
             },
-            PgOid::BuiltIn(PgBuiltInOids::FLOAT8ARRAYOID) => unsafe {
-                Vec::<Option<f64>>::from_datum(datum, false).map(AnyDatum::F64Array)
+            PgOid::BuiltIn(PgBuiltInOids::XMLOID) => unsafe { String::from_datum(datum, is_null).map(AnyDatum::Xml) },
+            PgOid::BuiltIn(PgBuiltInOids::MONEYOID) => unsafe { i64::from_datum(datum, is_null).map(AnyDatum::Money) },
+            PgOid::BuiltIn(PgBuiltInOids::TIMETZOID) => unsafe {
+                TimeWithTimeZone::from_datum(datum, is_null).map(AnyDatum::Timetz)
             },
-            PgOid::BuiltIn(PgBuiltInOids::TEXTARRAYOID) => unsafe {
-                Vec::<Option<String>>::from_datum(datum, false).map(AnyDatum::StringArray)
+            PgOid::BuiltIn(PgBuiltInOids::OIDOID) => unsafe {
+                pg_sys::Oid::from_datum(datum, is_null).map(AnyDatum::Oid)
             },
-            PgOid::BuiltIn(PgBuiltInOids::VARCHAROID) => unsafe {
-                String::from_datum(datum, is_null).map(AnyDatum::String)
+            _ if is_null => None,
+            // Values of a domain are values of its base type, e.g. text for a domain over text
+            _ if unsafe { pg_sys::get_typtype(typoid) } == pg_sys::TYPTYPE_DOMAIN as c_char => unsafe {
+                AnyDatum::from_polymorphic_datum(datum, false, pg_sys::getBaseType(typoid))
             },
-            _ => None,
+            _ if unsafe { pg_sys::type_is_enum(typoid) } => Some(AnyDatum::Enum(EnumDatum {
+                label_oid: unsafe { pg_sys::Oid::from_datum(datum, false) }?,
+            })),
+            _ if unsafe { pg_sys::get_element_type(typoid) } != pg_sys::InvalidOid => {
+                Some(AnyDatum::Array(unsafe { ArrayDatum::from_datum(datum) }))
+            }
+            _ if unsafe { pg_sys::get_typtype(typoid) } == pg_sys::TYPTYPE_RANGE as c_char => {
+                Some(AnyDatum::Range(unsafe { RangeDatum::from_datum(datum) }))
+            }
+            #[cfg(not(feature = "pg13"))]
+            _ if unsafe { pg_sys::get_typtype(typoid) } == pg_sys::TYPTYPE_MULTIRANGE as c_char => {
+                Some(AnyDatum::Multirange(unsafe {
+                    MultirangeDatum::from_datum(datum, typoid)
+                }))
+            }
+            _ if unsafe { pg_sys::type_is_rowtype(typoid) } => {
+                Some(AnyDatum::Composite(unsafe { CompositeDatum::from_datum(datum) }))
+            }
+            _ => Some(AnyDatum::Raw(unsafe { RawDatum::from_datum(datum, typoid) })),
         }
     }
 }
 
-fn write_array<T: std::fmt::Display>(array: &[Option<T>], f: &mut fmt::Formatter<'_>) -> fmt::Result {
-    let res = array
-        .iter()
-        .map(|e| match e {
-            Some(val) => format!("{val}",),
-            None => "null".to_owned(),
-        })
-        .collect::<Vec<String>>()
-        .join(",");
-    write!(f, "[{res}]",)
+// Write elements in nested brackets, one level per dimension
+fn write_array(elements: &[Option<AnyDatum>], dims: &[i32], f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let Some((_, inner_dims)) = dims.split_first() else {
+        return write!(f, "[]");
+    };
+
+    let chunk_size = inner_dims.iter().product::<i32>().max(1) as usize;
+
+    write!(f, "[")?;
+    for (i, chunk) in elements.chunks(chunk_size).enumerate() {
+        if i > 0 {
+            write!(f, ",")?;
+        }
+
+        match (inner_dims.is_empty(), &chunk[0]) {
+            (false, _) => write_array(chunk, inner_dims, f)?,
+            (true, Some(val)) => write!(f, "{val}")?,
+            (true, None) => write!(f, "null")?,
+        }
+    }
+    write!(f, "]")
 }
 
 impl fmt::Display for AnyDatum {
@@ -280,13 +901,120 @@ impl fmt::Display for AnyDatum {
             AnyDatum::Interval(v) => write!(f, "{v}"),
             AnyDatum::Json(v) => write!(f, "{v:?}"),
             AnyDatum::Uuid(v) => write!(f, "'{v}'",),
-            AnyDatum::BoolArray(v) => write_array(v, f),
-            AnyDatum::I16Array(v) => write_array(v, f),
-            AnyDatum::I32Array(v) => write_array(v, f),
-            AnyDatum::I64Array(v) => write_array(v, f),
-            AnyDatum::F32Array(v) => write_array(v, f),
-            AnyDatum::F64Array(v) => write_array(v, f),
-            AnyDatum::StringArray(v) => write_array(v, f),
+            AnyDatum::Bytea(v) => {
+                write!(f, "'\\x")?;
+                for byte in v {
+                    write!(f, "{byte:02x}")?;
+                }
+                write!(f, "'")
+            }
+            AnyDatum::Inet(v) => write!(f, "'{}'", v.0),
+            AnyDatum::Cidr(v) => write!(f, "'{v}'"),
+            AnyDatum::MacAddr(v) => write!(f, "'{v}'"),
+            AnyDatum::Bit(v) => write!(f, "B'{v}'"),
+            AnyDatum::VarBit(v) => write!(f, "B'{v}'"),
+            AnyDatum::Bpchar(v) => write!(f, "'{v}'"),
+            AnyDatum::Name(v) => write!(f, "'{v}'"),
+            AnyDatum::JsonText(v) => write!(f, "'{v}'"),
+            AnyDatum::Xml(v) => write!(f, "'{v}'"),
+            AnyDatum::Money(v) => write!(f, "'{}'", unsafe {
+                type_output(pg_sys::Datum::from(*v), pg_sys::MONEYOID)
+            }),
+            AnyDatum::Timetz(v) => write!(f, "'{}'", unsafe {
+                type_output(
+                    (*v).into_datum().expect("timetz should convert into a datum"),
+                    pg_sys::TIMETZOID,
+                )
+            }),
+            AnyDatum::Oid(v) => write!(f, "{}", v.to_u32()),
+            AnyDatum::Enum(v) => write!(f, "{v}"),
+            AnyDatum::Array(v) => write!(f, "{v}"),
+            AnyDatum::Range(v) => write!(f, "{v}"),
+            #[cfg(not(feature = "pg13"))]
+            AnyDatum::Multirange(v) => write!(f, "{v}"),
+            AnyDatum::Composite(v) => write!(f, "{v}"),
+            AnyDatum::Raw(v) => write!(f, "{v}"),
         }
     }
 }
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pgrx::pg_schema]
+mod tests {
+    use super::AnyDatum;
+    use pgrx::prelude::*;
+
+    fn render(expression: &str) -> String {
+        Spi::get_one::<AnyDatum>(&format!("select {expression}"))
+            .expect("query should succeed")
+            .expect("value should not be null")
+            .to_string()
+    }
+
+    #[pg_test]
+    fn test_display() {
+        Spi::run(
+            r#"
+                set local timezone = 'UTC';
+                set local datestyle = 'ISO, MDY';
+                set local intervalstyle = 'postgres';
+                set local lc_monetary = 'C';
+                create type mood as enum ('happy', 'sad');
+                create type pair as (n int, s text);
+            "#,
+        )
+        .expect("setup should succeed");
+
+        assert_eq!(render("true"), "true");
+        assert_eq!(render(r#"'a'::"char""#), "97");
+        assert_eq!(render("2::int2"), "2");
+        assert_eq!(render("1.5::float4"), "1.5");
+        assert_eq!(render("3::int4"), "3");
+        assert_eq!(render("2.25::float8"), "2.25");
+        assert_eq!(render("4::int8"), "4");
+        assert_eq!(render("1.50::numeric"), "1.50");
+        assert_eq!(render("'text'::text"), "'text'");
+        assert_eq!(render("'2024-01-02'::date"), "'2024-01-02'");
+        assert_eq!(render("'12:34:56'::time"), "'12:34:56'");
+        assert_eq!(render("'2024-01-02 12:34:56'::timestamp"), "'2024-01-02 12:34:56'");
+        assert_eq!(
+            render("'2024-01-02 12:34:56+00'::timestamptz"),
+            "'2024-01-02 12:34:56+00'"
+        );
+        assert_eq!(render("'1 day 2 hours'::interval"), "1 day 02:00:00");
+        assert_eq!(render("'1'::jsonb"), "JsonB(Number(1))");
+        assert_eq!(
+            render("'a0eebc99-9c0b-4ef8-bb6d-6bb9bd380a11'::uuid"),
+            "'a0eebc99-9c0b-4ef8-bb6d-6bb9bd380a11'"
+        );
+        assert_eq!(render(r#"'\x0102ff'::bytea"#), r#"'\x0102ff'"#);
+        assert_eq!(render("'192.168.0.1'::inet"), "'192.168.0.1'");
+        assert_eq!(render("'10.0.0.0/8'::cidr"), "'10.0.0.0/8'");
+        assert_eq!(render("'08:00:2b:01:02:03'::macaddr"), "'08:00:2b:01:02:03'");
+        assert_eq!(render("B'101'"), "B'101'");
+        assert_eq!(render("B'1010'::varbit"), "B'1010'");
+        assert_eq!(render("'ab'::char(4)"), "'ab  '");
+        assert_eq!(render("'abc'::name"), "'abc'");
+        assert_eq!(render(r#"'{"a": 1}'::json"#), r#"'{"a": 1}'"#);
+        assert_eq!(render("'1.5'::numeric::money"), "'$1.50'");
+        assert_eq!(render("'12:34:56+02'::timetz"), "'12:34:56+02'");
+        assert_eq!(render("42::oid"), "42");
+        assert_eq!(render("'happy'::mood"), "'happy'");
+        assert_eq!(render("array[[1, 2], [3, null]]"), "[[1,2],[3,null]]");
+        assert_eq!(render("'[0:1]={1,2}'::int4[]"), "[0:1]=[1,2]");
+        assert_eq!(render("int4range(1, 10)"), "[1,10)");
+        assert_eq!(render("'empty'::numrange"), "empty");
+        assert_eq!(
+            render("tstzrange(null, '2024-01-02 00:00:00+00')"),
+            "(,'2024-01-02 00:00:00+00')"
+        );
+        #[cfg(not(feature = "pg13"))]
+        assert_eq!(
+            render("int4multirange(int4range(1, 3), int4range(5, 7))"),
+            "{[1,3),[5,7)}"
+        );
+        assert_eq!(render("row(1, 'a')"), "ROW(1, 'a')");
+        assert_eq!(render("row(1, null)::pair"), "ROW(1, null)::pair");
+        assert_eq!(render("point(1, 2)"), "'(1,2)'");
+    }
+}
@@ -0,0 +1,156 @@
+use crate::err::{PlprqlError, PlprqlResult};
+use prqlc::ir::rq::{ExprKind, RelationKind, RelationalQuery, RqFold, fold_expr_kind, fold_relation_kind};
+use prqlc_parser::generic::InterpolateItem;
+
+// Bind parameters that refer to arguments by name, e.g. `$planet` for an argument `planet`, to the argument's position,
+// e.g. `$1`, which is how the query's parameters are passed. Positional parameters are left as they are. Names are
+// bound in the SQL text of s-strings too, so `s"{id} = ANY($ids)"` works like `s"{id} = ANY($1)"`.
+pub(crate) fn bind_arguments(
+    query: RelationalQuery,
+    argument_names: &[Option<String>],
+) -> PlprqlResult<RelationalQuery> {
+    let mut binder = Binder {
+        argument_names,
+        undefined: None,
+    };
+
+    let query = binder.fold_query(query).map_err(prqlc::ErrorMessages::from)?;
+    match binder.undefined {
+        Some(name) => Err(PlprqlError::UndefinedArgument { name }),
+        None => Ok(query),
+    }
+}
+
+struct Binder<'a> {
+    argument_names: &'a [Option<String>],
+    undefined: Option<String>,
+}
+
+impl Binder<'_> {
+    fn position(&self, name: &str) -> Option<usize> {
+        self.argument_names
+            .iter()
+            .position(|argument_name| argument_name.as_deref() == Some(name))
+            .map(|index| index + 1)
+    }
+
+    fn bind_param(&mut self, name: String) -> String {
+        if name.parse::<usize>().is_ok() {
+            return name;
+        }
+
+        match self.position(&name) {
+            Some(position) => position.to_string(),
+            None => {
+                self.undefined.get_or_insert(name.clone());
+                name
+            }
+        }
+    }
+
+    // Replace `$name` in SQL text. Quoted identifiers, string literals and dollar-quoted strings are copied as they are,
+    // so e.g. `'$name'` stays text, and so is a `$` inside an identifier like `price$name`. Unknown names are left as
+    // they are too.
+    fn bind_sql(&self, sql: String) -> String {
+        let mut bound = String::with_capacity(sql.len());
+        let mut rest = sql.as_str();
+
+        while let Some(start) = rest.find(['$', '\'', '"']) {
+            bound.push_str(&rest[..start]);
+
+            if let Some(quote) = rest[start..].chars().next().filter(|c| *c != '$') {
+                let escapes = quote == '\'' && is_escape_string_prefix(&bound);
+                let end = start + 1 + quoted_length(&rest[start + 1..], quote, escapes);
+                bound.push_str(&rest[start..end]);
+                rest = &rest[end..];
+                continue;
+            }
+
+            if bound.ends_with(is_identifier_char) {
+                bound.push('$');
+                rest = &rest[start + 1..];
+                continue;
+            }
+
+            rest = &rest[start + 1..];
+            let end = rest
+                .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            let name = &rest[..end];
+
+            if rest[end..].starts_with('$') && !name.starts_with(|c: char| c.is_ascii_digit()) {
+                let tag = &rest[..=end];
+                let end = rest[end + 1..]
+                    .find(&format!("${tag}"))
+                    .map_or(rest.len(), |body| end + 1 + body + tag.len() + 1);
+                bound.push('$');
+                bound.push_str(&rest[..end]);
+                rest = &rest[end..];
+                continue;
+            }
+
+            match self.position(name) {
+                Some(position) => bound.push_str(&format!("${position}")),
+                None => bound.push_str(&format!("${name}")),
+            }
+            rest = &rest[end..];
+        }
+
+        bound.push_str(rest);
+        bound
+    }
+
+    fn bind_interpolation<T>(&self, items: Vec<InterpolateItem<T>>) -> Vec<InterpolateItem<T>> {
+        items
+            .into_iter()
+            .map(|item| match item {
+                InterpolateItem::String(sql) => InterpolateItem::String(self.bind_sql(sql)),
+                item => item,
+            })
+            .collect()
+    }
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '$'
+}
+
+// Whether a string literal that follows the given SQL text is an escape string like `E'it\'s'`
+fn is_escape_string_prefix(sql: &str) -> bool {
+    let mut chars = sql.chars().rev();
+    matches!(chars.next(), Some('E' | 'e')) && !chars.next().is_some_and(is_identifier_char)
+}
+
+// Length of the rest of a quoted identifier or string literal, up to and including its closing quote. A doubled quote
+// stands for the quote itself, as does a quote after a backslash in escape strings. Unclosed text runs to the end.
+fn quoted_length(text: &str, quote: char, escapes: bool) -> usize {
+    let mut chars = text.char_indices().peekable();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '\\' if escapes => _ = chars.next(),
+            c if c == quote && chars.peek().is_some_and(|(_, next)| *next == quote) => _ = chars.next(),
+            c if c == quote => return index + 1,
+            _ => {}
+        }
+    }
+    text.len()
+}
+
+impl RqFold for Binder<'_> {
+    fn fold_expr_kind(&mut self, kind: ExprKind) -> prqlc::Result<ExprKind> {
+        match kind {
+            ExprKind::Param(name) => Ok(ExprKind::Param(self.bind_param(name))),
+            ExprKind::SString(items) => fold_expr_kind(self, ExprKind::SString(self.bind_interpolation(items))),
+            kind => fold_expr_kind(self, kind),
+        }
+    }
+
+    fn fold_relation_kind(&mut self, kind: RelationKind) -> prqlc::Result<RelationKind> {
+        match kind {
+            RelationKind::SString(items) => {
+                fold_relation_kind(self, RelationKind::SString(self.bind_interpolation(items)))
+            }
+            kind => fold_relation_kind(self, kind),
+        }
+    }
+}
@@ -0,0 +1,233 @@
+use crate::err::PlprqlResult;
+use crate::fun::{Function, Return};
+use crate::plprql::function_to_sql;
+use crate::trigger;
+use pgrx::prelude::*;
+use pgrx::{IntoDatum, pg_sys};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::{CString, c_int};
+use std::ptr::NonNull;
+use std::rc::Rc;
+
+unsafe extern "C-unwind" {
+    // Declared in utils/inval.h which pgrx does not generate bindings for
+    fn CacheRegisterSyscacheCallback(
+        cacheid: c_int,
+        func: unsafe extern "C-unwind" fn(pg_sys::Datum, c_int, u32),
+        arg: pg_sys::Datum,
+    );
+}
+
+// A plan kept with SPI_keepplan, so it outlives the SPI connection it was prepared on. Unlike pgrx's prepared
+// statements it gives access to the SPIPlanPtr, which e.g. SPI_cursor_open needs to open a portal under a given name.
+pub(crate) struct Plan(NonNull<pg_sys::_SPI_plan>);
+
+impl Plan {
+    pub(crate) fn as_ptr(&self) -> pg_sys::SPIPlanPtr {
+        self.0.as_ptr()
+    }
+}
+
+impl Drop for Plan {
+    fn drop(&mut self) {
+        unsafe { pg_sys::SPI_freeplan(self.0.as_ptr()) };
+    }
+}
+
+// Compiled SQL and saved plan of a function along with the version of the pg_proc row it was compiled from
+struct Entry {
+    hash_value: u32,
+    xmin: pg_sys::TransactionId,
+    tid: pg_sys::ItemPointerData,
+    sql: Option<Rc<str>>,
+    plan: Option<Rc<Plan>>,
+    argument_types: Vec<pg_sys::Oid>,
+    hits: i64,
+    misses: i64,
+    invalidations: i64,
+    prepares: i64,
+}
+
+thread_local! {
+    // Backends are single-threaded, so the cache is local to the backend and lives for the rest of the session
+    static CACHE: RefCell<HashMap<pg_sys::Oid, Entry>> = RefCell::new(HashMap::new());
+}
+
+// Invalidate entries when their pg_proc rows change, e.g. on `create or replace function` or `drop function`
+pub(crate) fn init() {
+    unsafe {
+        pg_sys::ffi::pg_guard_ffi_boundary(|| {
+            CacheRegisterSyscacheCallback(
+                pg_sys::SysCacheIdentifier::PROCOID as c_int,
+                invalidate,
+                pg_sys::Datum::from(0),
+            )
+        })
+    };
+}
+
+#[pg_guard]
+unsafe extern "C-unwind" fn invalidate(_arg: pg_sys::Datum, _cache_id: c_int, hash_value: u32) {
+    // A hash value of zero means that all entries must be invalidated. Saved plans are not freed here, but replaced
+    // when the function is recompiled on its next call.
+    CACHE.with_borrow_mut(|cache| {
+        cache
+            .values_mut()
+            .filter(|entry| hash_value == 0 || entry.hash_value == hash_value)
+            .filter(|entry| entry.sql.is_some())
+            .for_each(|entry| {
+                entry.sql = None;
+                entry.invalidations += 1;
+            })
+    });
+}
+
+// Identify the version of a function's pg_proc row the same way PL/pgSQL does, by the row's xmin and TID
+fn version(function_oid: pg_sys::Oid) -> Option<(pg_sys::TransactionId, pg_sys::ItemPointerData)> {
+    unsafe {
+        let tuple = pg_sys::SearchSysCache1(pg_sys::SysCacheIdentifier::PROCOID as _, function_oid.into_datum()?);
+        if tuple.is_null() {
+            return None;
+        }
+
+        let xmin = (*(*tuple).t_data).t_choice.t_heap.t_xmin;
+        let tid = (*tuple).t_self;
+        pg_sys::ReleaseSysCache(tuple);
+        Some((xmin, tid))
+    }
+}
+
+// Compile a function's PRQL to the SQL that is prepared for its calls
+fn to_sql(function: &Function) -> PlprqlResult<String> {
+    let sql = function_to_sql(&function.body(), &function.argument_names())?;
+    match function.return_mode() {
+        Return::Trigger => Ok(trigger::wrap(&sql)),
+        _ => Ok(sql),
+    }
+}
+
+// Get the compiled SQL of a function, compiling the PRQL only if the function is new or has changed
+pub(crate) fn compile(function: &Function) -> PlprqlResult<Rc<str>> {
+    let function_oid = function.pg_proc.oid();
+
+    // Catalog lookups may process invalidation messages which borrow the cache, so look up everything up front
+    let Some((xmin, mut tid)) = version(function_oid) else {
+        return Ok(to_sql(function)?.into());
+    };
+
+    let hash_value = unsafe {
+        pg_sys::GetSysCacheHashValue(
+            pg_sys::SysCacheIdentifier::PROCOID as c_int,
+            function_oid.into_datum().unwrap_or_else(|| pg_sys::Datum::from(0)),
+            pg_sys::Datum::from(0),
+            pg_sys::Datum::from(0),
+            pg_sys::Datum::from(0),
+        )
+    };
+
+    let cached = CACHE.with_borrow_mut(|cache| {
+        let entry = cache.get_mut(&function_oid)?;
+        let is_current = entry.xmin == xmin && unsafe { pg_sys::ItemPointerEquals(&mut entry.tid, &mut tid) };
+        let sql = entry.sql.clone().filter(|_| is_current)?;
+        entry.hits += 1;
+        Some(sql)
+    });
+
+    if let Some(sql) = cached {
+        return Ok(sql);
+    }
+
+    let sql: Rc<str> = to_sql(function)?.into();
+
+    let stale_plan = CACHE.with_borrow_mut(|cache| {
+        let entry = cache.entry(function_oid).or_insert_with(|| Entry {
+            hash_value,
+            xmin,
+            tid,
+            sql: None,
+            plan: None,
+            argument_types: vec![],
+            hits: 0,
+            misses: 0,
+            invalidations: 0,
+            prepares: 0,
+        });
+
+        entry.hash_value = hash_value;
+        entry.xmin = xmin;
+        entry.tid = tid;
+        entry.sql = Some(sql.clone());
+        entry.misses += 1;
+        entry.plan.take()
+    });
+
+    // Free the plan of the previous version of the function outside of the borrow
+    drop(stale_plan);
+
+    Ok(sql)
+}
+
+// Get the saved plan of a function, preparing the compiled SQL only if the function is new or has changed. The plan
+// is kept with SPI_keepplan, so PostgreSQL's plan cache revalidates it and applies plan_cache_mode on execution. SPI
+// must be connected.
+pub(crate) fn prepare(function: &Function) -> PlprqlResult<Rc<Plan>> {
+    let function_oid = function.pg_proc.oid();
+    let sql = compile(function)?;
+    let mut argument_oids = function
+        .argument_types()
+        .iter()
+        .map(|oid| oid.value())
+        .collect::<Vec<_>>();
+
+    let cached = CACHE.with_borrow(|cache| {
+        let entry = cache.get(&function_oid)?;
+        entry.plan.clone().filter(|_| entry.argument_types == argument_oids)
+    });
+
+    if let Some(plan) = cached {
+        return Ok(plan);
+    }
+
+    let sql = CString::new(&*sql).expect("query contained a null byte");
+    let plan = unsafe {
+        let plan = pg_sys::SPI_prepare(sql.as_ptr(), argument_oids.len() as c_int, argument_oids.as_mut_ptr());
+        let Some(plan) = NonNull::new(plan) else {
+            Spi::check_status(pg_sys::SPI_result)?;
+            unreachable!("SPI_prepare should report why it returned no plan");
+        };
+        pg_sys::SPI_keepplan(plan.as_ptr());
+        Rc::new(Plan(plan))
+    };
+
+    let stale_plan = CACHE.with_borrow_mut(|cache| {
+        let entry = cache.get_mut(&function_oid)?;
+        entry.argument_types = argument_oids;
+        entry.prepares += 1;
+        entry.plan.replace(plan.clone())
+    });
+
+    drop(stale_plan);
+
+    Ok(plan)
+}
+
+// Hits, misses, invalidations and prepared plans of each function that has been called in this session
+pub(crate) fn stats() -> Vec<(pg_sys::Oid, i64, i64, i64, i64)> {
+    CACHE.with_borrow(|cache| {
+        let mut stats = cache
+            .iter()
+            .map(|(function_oid, entry)| {
+                (
+                    *function_oid,
+                    entry.hits,
+                    entry.misses,
+                    entry.invalidations,
+                    entry.prepares,
+                )
+            })
+            .collect::<Vec<_>>();
+        stats.sort_by_key(|(function_oid, ..)| function_oid.to_u32());
+        stats
+    })
+}
@@ -0,0 +1,76 @@
+use crate::err::{PlprqlError, PlprqlResult};
+use crate::fun::type_name;
+use pgrx::{PgBox, pg_sys};
+
+// An assignment cast of values from a column of a query to a column of the function's return type, e.g. from bigint
+// to int. The cast is built the way PL/pgSQL builds the casts of its assignments, as an expression over a placeholder
+// for the value, so it covers cast functions, I/O conversions, typmods, arrays and domain constraints alike. The
+// expression state is allocated in the current memory context and freed along with it, while the expression context
+// is freed when the cast is dropped.
+pub(crate) struct Cast {
+    expr_state: *mut pg_sys::ExprState,
+    econtext: *mut pg_sys::ExprContext,
+}
+
+impl Cast {
+    pub(crate) fn new(
+        position: usize,
+        source: pg_sys::Oid,
+        source_typmod: i32,
+        target: pg_sys::Oid,
+        target_typmod: i32,
+    ) -> PlprqlResult<Self> {
+        unsafe {
+            let mut placeholder = PgBox::<pg_sys::CaseTestExpr>::alloc_node(pg_sys::NodeTag::T_CaseTestExpr);
+            placeholder.typeId = source;
+            placeholder.typeMod = source_typmod;
+            placeholder.collation = pg_sys::get_typcollation(source);
+
+            let expr = pg_sys::coerce_to_target_type(
+                std::ptr::null_mut(),
+                placeholder.into_pg() as *mut pg_sys::Node,
+                source,
+                target,
+                target_typmod,
+                pg_sys::CoercionContext::COERCION_ASSIGNMENT,
+                pg_sys::CoercionForm::COERCE_IMPLICIT_CAST,
+                -1,
+            );
+
+            if expr.is_null() {
+                return Err(PlprqlError::UncastableColumn {
+                    position,
+                    expected: type_name(target),
+                    actual: type_name(source),
+                });
+            }
+
+            let expr = pg_sys::expression_planner(expr as *mut pg_sys::Expr);
+            Ok(Cast {
+                expr_state: pg_sys::ExecInitExpr(expr, std::ptr::null_mut()),
+                econtext: pg_sys::CreateStandaloneExprContext(),
+            })
+        }
+    }
+
+    // Cast a value, or null, in the current memory context. Nulls are cast too, so e.g. a NOT NULL domain rejects them.
+    pub(crate) fn apply(&self, datum: Option<pg_sys::Datum>) -> Option<pg_sys::Datum> {
+        unsafe {
+            (*self.econtext).caseValue_datum = datum.unwrap_or_else(|| pg_sys::Datum::from(0));
+            (*self.econtext).caseValue_isNull = datum.is_none();
+
+            let evaluate = (*self.expr_state)
+                .evalfunc
+                .expect("expression state should have been initialized");
+            let mut is_null = false;
+            let result = pg_sys::ffi::pg_guard_ffi_boundary(|| evaluate(self.expr_state, self.econtext, &mut is_null));
+            (!is_null).then_some(result)
+        }
+    }
+}
+
+impl Drop for Cast {
+    fn drop(&mut self) {
+        unsafe { pg_sys::FreeExprContext(self.econtext, true) };
+    }
+}
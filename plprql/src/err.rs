@@ -11,6 +11,57 @@ pub enum PlprqlError {
     #[error("FmgrInfo is null")]
     NullFmgrInfo,
 
+    #[error("InlineCodeBlock is null")]
+    NullInlineCodeBlock,
+
+    #[error("Function returns {expected} columns, but query returns {actual} columns")]
+    ColumnCountMismatch { expected: usize, actual: usize },
+
+    #[error("Function returns column \"{name}\", but query does not return it")]
+    MissingColumn { name: String },
+
+    #[error("Query returns column \"{name}\", but function does not return it")]
+    ExtraColumn { name: String },
+
+    #[error("Column {position} (\"{name}\") of query has type {actual}, but function returns {expected}")]
+    ColumnTypeMismatch {
+        position: usize,
+        name: String,
+        expected: String,
+        actual: String,
+    },
+
+    #[error("Column {position}: cannot convert {actual} to {expected}")]
+    UncastableColumn {
+        position: usize,
+        expected: String,
+        actual: String,
+    },
+
+    #[error("VARIADIC \"any\" arguments are not supported, use a VARIADIC array of a specific or polymorphic type")]
+    VariadicAny,
+
+    #[error("Function has no argument named \"{name}\"")]
+    UndefinedArgument { name: String },
+
+    #[error("\"{statement}\" on line {line} is part of a pipeline; separate it from pipelines with blank lines")]
+    TransactionControlInPipeline { statement: String, line: usize },
+
+    #[error("Query is null")]
+    NullQuery,
+
+    #[error("Parameters must be a JSON object")]
+    ParametersNotObject,
+
+    #[error("No parameter named \"{name}\" is given")]
+    UndefinedParameter { name: String },
+
+    #[error("Parameter ${position} is not given; use named parameters with a jsonb argument")]
+    UnnamedParameter { position: usize },
+
+    #[error("Parameter \"{name}\" is a JSON array or object, but the query uses it as {expected}")]
+    NonScalarParameter { name: String, expected: String },
+
     #[error(transparent)] // delegate Display to PGRX
     PgrxError(#[from] pgrx::spi::Error),
 
@@ -1,12 +1,19 @@
-use pgrx::pg_catalog::pg_proc::{PgProc, ProArgMode};
+use pgrx::PgTupleDesc;
+use pgrx::pg_catalog::pg_proc::{PgProc, ProArgMode, ProKind};
 use pgrx::prelude::*;
 
 use crate::err::{PlprqlError, PlprqlResult};
+use crate::guc;
+use crate::trigger;
 
 pub enum Return {
     Table,
     SetOf,
+    Composite,
     Scalar,
+    Cursor,
+    Trigger,
+    Procedure,
 }
 
 pub struct Function {
@@ -27,18 +34,89 @@ impl Function {
         .fn_oid;
 
         Ok(Self {
-            pg_proc: PgProc::new(function_oid).ok_or(PlprqlError::UndefinedFunction)?,
             call_info: function_call_info,
+            ..Self::from_oid(function_oid)?
         })
     }
 
-    pub fn arguments(&self) -> PlprqlResult<Option<Vec<pgrx::datum::DatumWithOid<'static>>>> {
-        let argument_types = self
-            .pg_proc
+    // Look up a function without a call, e.g. when validating it. Arguments are not available.
+    pub fn from_oid(function_oid: pg_sys::Oid) -> Result<Self, PlprqlError> {
+        Ok(Self {
+            pg_proc: PgProc::new(function_oid).ok_or(PlprqlError::UndefinedFunction)?,
+            call_info: std::ptr::null_mut(),
+        })
+    }
+
+    // Types of the input arguments. Polymorphic arguments like `anyelement` have the actual types of the call, found
+    // in the call's expression, so the query can be prepared with them. Without a call, the declared types are used.
+    pub fn argument_types(&self) -> Vec<PgOid> {
+        if let Some(trigger_data) = self.trigger_data() {
+            return trigger::argument_types(trigger_data);
+        }
+
+        let flinfo = unsafe { self.call_info.as_ref() }.map_or(std::ptr::null_mut(), |call_info| call_info.flinfo);
+
+        self.pg_proc
             .proargtypes()
             .into_iter()
+            .enumerate()
+            .map(
+                |(index, declared)| match flinfo.is_null() || !is_pseudo_type(declared) {
+                    true => declared,
+                    false => match unsafe { pg_sys::get_fn_expr_argtype(flinfo, index as i32) } {
+                        pg_sys::InvalidOid => declared,
+                        actual => actual,
+                    },
+                },
+            )
             .map(PgOid::from)
-            .collect::<Vec<_>>();
+            .collect::<Vec<_>>()
+    }
+
+    // Names of the input arguments, in the order of their types. None for arguments without a name.
+    pub fn argument_names(&self) -> Vec<Option<String>> {
+        self.pg_proc
+            .proargnames()
+            .into_iter()
+            .zip(self.pg_proc.proargmodes())
+            .filter(|(_, mode)| self.is_input(mode))
+            .map(|(name, _)| name.filter(|name| !name.is_empty()))
+            .collect()
+    }
+
+    // The return type of a call. A polymorphic return type like `anyelement` is resolved from the actual types of the
+    // call's arguments.
+    pub fn return_type(&self) -> pg_sys::Oid {
+        let declared = self.pg_proc.prorettype();
+        if self.call_info.is_null() || !is_pseudo_type(declared) {
+            return declared;
+        }
+
+        let mut return_type = pg_sys::InvalidOid;
+        unsafe { pg_sys::get_call_result_type(self.call_info, &mut return_type, std::ptr::null_mut()) };
+        match return_type {
+            pg_sys::InvalidOid => declared,
+            actual => actual,
+        }
+    }
+
+    // A variadic argument like `variadic ids int[]` is collected into a single array, whether it is called with a list
+    // of values or with `variadic array[...]`, and is bound as one parameter like any other array. Values passed to
+    // `variadic "any"` are not collected, so they cannot be bound to the parameters of the function's query.
+    pub fn check_variadic(&self) -> PlprqlResult<()> {
+        match self.pg_proc.provariadic() {
+            Some(pg_sys::ANYOID) => Err(PlprqlError::VariadicAny),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn arguments(&self) -> PlprqlResult<Option<Vec<pgrx::datum::DatumWithOid<'static>>>> {
+        if let Some(trigger_data) = self.trigger_data() {
+            return Ok(Some(trigger::arguments(trigger_data)));
+        }
+
+        self.check_variadic()?;
+        let argument_types = self.argument_types();
 
         let argument_values = unsafe {
             self.call_info
@@ -71,18 +149,256 @@ impl Function {
         }
     }
 
+    // Types of the output parameters, i.e. OUT, INOUT and TABLE arguments
+    pub fn output_types(&self) -> Vec<pg_sys::Oid> {
+        self.pg_proc
+            .proallargtypes()
+            .into_iter()
+            .zip(self.pg_proc.proargmodes())
+            .filter(|(_, mode)| matches!(mode, ProArgMode::Out | ProArgMode::InOut | ProArgMode::Table))
+            .map(|(type_oid, _)| type_oid)
+            .collect()
+    }
+
+    // Position among the input arguments of the first refcursor argument, whose value names the cursor that a function
+    // returning refcursor opens. For a procedure, only an INOUT refcursor names the cursor, as the value of an OUT
+    // argument is just a placeholder.
+    pub fn cursor_argument(&self) -> Option<usize> {
+        if matches!(self.pg_proc.prokind(), ProKind::Procedure) {
+            return self
+                .pg_proc
+                .proallargtypes()
+                .into_iter()
+                .zip(self.pg_proc.proargmodes())
+                .filter(|(_, mode)| self.is_input(mode))
+                .position(|(type_oid, mode)| type_oid == pg_sys::REFCURSOROID && mode == ProArgMode::InOut);
+        }
+
+        self.argument_types()
+            .iter()
+            .position(|oid| oid.value() == pg_sys::REFCURSOROID)
+    }
+
+    // Whether an argument is passed in the call. OUT arguments of procedures are, as placeholders for their values.
+    fn is_input(&self, mode: &ProArgMode) -> bool {
+        match mode {
+            ProArgMode::In | ProArgMode::InOut | ProArgMode::Variadic => true,
+            ProArgMode::Out => matches!(self.pg_proc.prokind(), ProKind::Procedure),
+            _ => false,
+        }
+    }
+
+    // Whether a procedure is called in an atomic context, i.e. without transaction control. Only CALL outside of a
+    // transaction block is non-atomic.
+    pub fn is_atomic(&self) -> bool {
+        let Some(context) = unsafe { self.call_info.as_ref() }.map(|call_info| call_info.context) else {
+            return true;
+        };
+
+        match !context.is_null() && unsafe { pgrx::is_a(context, pg_sys::NodeTag::T_CallContext) } {
+            true => unsafe { (*(context as *mut pg_sys::CallContext)).atomic },
+            false => true,
+        }
+    }
+
+    // The trigger event if the function is called as a trigger
+    pub fn trigger_data(&self) -> Option<*mut pg_sys::TriggerData> {
+        let context = unsafe { self.call_info.as_ref() }?.context;
+        let is_trigger = !context.is_null() && unsafe { pgrx::is_a(context, pg_sys::NodeTag::T_TriggerData) };
+        is_trigger.then_some(context as *mut pg_sys::TriggerData)
+    }
+
     pub fn body(&self) -> String {
         self.pg_proc.prosrc()
     }
 
+    // Name and type of the columns declared by the return type. None if the columns are not known until the function
+    // is called, e.g. for `returns setof record` or pseudo-types like `anyelement`.
+    pub fn result_columns(&self) -> Option<Vec<(String, pg_sys::Oid)>> {
+        // get_func_result_type fails on polymorphic return and output types, which it resolves from a call's arguments
+        let declared_types = std::iter::once(self.pg_proc.prorettype()).chain(self.output_types());
+        if declared_types
+            .filter(|type_oid| *type_oid != pg_sys::RECORDOID)
+            .any(is_pseudo_type)
+        {
+            return None;
+        }
+
+        let mut return_type = pg_sys::Oid::INVALID;
+        let mut tupdesc: pg_sys::TupleDesc = std::ptr::null_mut();
+        let type_class = unsafe { pg_sys::get_func_result_type(self.pg_proc.oid(), &mut return_type, &mut tupdesc) };
+
+        match type_class {
+            pg_sys::TypeFuncClass::TYPEFUNC_COMPOSITE | pg_sys::TypeFuncClass::TYPEFUNC_COMPOSITE_DOMAIN => {
+                let tupdesc = unsafe { PgTupleDesc::from_pg_unchecked(tupdesc) };
+                Some(
+                    tupdesc
+                        .iter()
+                        .filter(|attribute| !attribute.is_dropped())
+                        .map(|attribute| (attribute.name().to_string(), attribute.type_oid().value()))
+                        .collect(),
+                )
+            }
+            pg_sys::TypeFuncClass::TYPEFUNC_SCALAR if !is_pseudo_type(return_type) => {
+                Some(vec![(String::new(), return_type)])
+            }
+            _ => None,
+        }
+    }
+
+    // Check that the name and type of the columns returned by a query fit the declared return type
+    pub fn check_result_columns(&self, columns: &[(String, pg_sys::Oid)]) -> PlprqlResult<()> {
+        let Some(result_columns) = self.result_columns() else {
+            return Ok(());
+        };
+
+        let column_types = columns.iter().map(|(_, type_oid)| *type_oid).collect::<Vec<_>>();
+        if matches!(self.return_mode(), Return::Composite) && self.is_whole_row(&column_types) {
+            return Ok(());
+        }
+
+        let by_name = self.match_columns_by_name();
+        let result_names = result_columns.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>();
+        let query_names = columns.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>();
+        let column_map = match_columns(&result_names, &query_names, by_name)?;
+
+        for (position, ((_, expected), index)) in result_columns.iter().zip(column_map).enumerate() {
+            let (name, actual) = &columns[index];
+            // Values are cast to the declared types when they are returned, so any assignment cast will do
+            let is_castable =
+                unsafe { pg_sys::can_coerce_type(1, actual, expected, pg_sys::CoercionContext::COERCION_ASSIGNMENT) };
+            if !is_castable {
+                return Err(PlprqlError::ColumnTypeMismatch {
+                    position: position + 1,
+                    name: name.clone(),
+                    expected: type_name(*expected),
+                    actual: type_name(*actual),
+                });
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn return_mode(&self) -> Return {
+        if matches!(self.pg_proc.prokind(), ProKind::Procedure) {
+            return Return::Procedure;
+        }
+
+        if self.pg_proc.prorettype() == pg_sys::TRIGGEROID {
+            return Return::Trigger;
+        }
+
         match (
             self.pg_proc.proretset(),
             self.pg_proc.proargmodes().contains(&ProArgMode::Table),
         ) {
             (true, true) => Return::Table,
+            // Rows of e.g. `setof people` have the composite type's columns, just like rows of `table(...)`, and rows of
+            // `setof record` have the columns of OUT parameters or of the caller's column definition list
+            (true, false) if self.returns_composite() => Return::Table,
             (true, false) => Return::SetOf,
+            // A single row, e.g. of `returns people`, `returns record` or a function with OUT parameters
+            (false, _) if self.returns_composite() => Return::Composite,
+            (false, _) if self.pg_proc.prorettype() == pg_sys::REFCURSOROID => Return::Cursor,
             (false, _) => Return::Scalar,
         }
     }
+
+    // Whether a query returns a function's single composite value as a whole rather than as its columns, e.g.
+    // `select {customer}` for `returns customer_t`. Functions with OUT parameters always get their columns.
+    pub fn is_whole_row(&self, column_types: &[pg_sys::Oid]) -> bool {
+        let return_type = self.pg_proc.prorettype();
+        let has_out_arguments = self
+            .pg_proc
+            .proargmodes()
+            .iter()
+            .any(|mode| matches!(mode, ProArgMode::Out | ProArgMode::InOut));
+
+        match column_types {
+            [column_type] if unsafe { pg_sys::type_is_rowtype(*column_type) } => match return_type {
+                pg_sys::RECORDOID => !has_out_arguments,
+                _ => unsafe { pg_sys::IsBinaryCoercible(*column_type, return_type) },
+            },
+            _ => false,
+        }
+    }
+
+    // Whether the columns of a function returning rows are matched by name. Calls apply the function's own
+    // `set plprql.match_columns_by_name` clause, but validation does not, so the function's settings are checked
+    // before the session's.
+    pub fn match_columns_by_name(&self) -> bool {
+        if !matches!(
+            self.return_mode(),
+            Return::Table | Return::Composite | Return::Trigger | Return::Procedure
+        ) {
+            return false;
+        }
+
+        let setting = self
+            .pg_proc
+            .proconfig()
+            .unwrap_or_default()
+            .into_iter()
+            .find_map(|config| config.strip_prefix("plprql.match_columns_by_name=").map(str::to_owned))
+            .and_then(|value| std::ffi::CString::new(value).ok());
+
+        let mut by_name = false;
+        match setting {
+            Some(value) if unsafe { pg_sys::parse_bool(value.as_ptr(), &mut by_name) } => by_name,
+            _ => guc::MATCH_COLUMNS_BY_NAME.get(),
+        }
+    }
+
+    fn returns_composite(&self) -> bool {
+        unsafe { pg_sys::type_is_rowtype(self.pg_proc.prorettype()) }
+    }
+}
+
+// Match the columns of a query's result to the function's result columns, by position or by name, and return the
+// index of the query column for each result column
+pub fn match_columns(result_names: &[&str], query_names: &[&str], by_name: bool) -> PlprqlResult<Vec<usize>> {
+    let column_count_mismatch = || PlprqlError::ColumnCountMismatch {
+        expected: result_names.len(),
+        actual: query_names.len(),
+    };
+
+    if !by_name {
+        return match result_names.len() == query_names.len() {
+            true => Ok((0..result_names.len()).collect()),
+            false => Err(column_count_mismatch()),
+        };
+    }
+
+    if let Some(name) = query_names.iter().find(|name| !result_names.contains(name)) {
+        return Err(PlprqlError::ExtraColumn { name: name.to_string() });
+    }
+
+    let column_map = result_names
+        .iter()
+        .map(|name| {
+            query_names
+                .iter()
+                .position(|query_name| query_name == name)
+                .ok_or_else(|| PlprqlError::MissingColumn { name: name.to_string() })
+        })
+        .collect::<PlprqlResult<Vec<_>>>()?;
+
+    // Every query column matches a result column, so a difference in count means that a name is repeated
+    match column_map.len() == query_names.len() {
+        true => Ok(column_map),
+        false => Err(column_count_mismatch()),
+    }
+}
+
+// Pseudo-types like `anyelement`, `record`, `trigger` and `void` cannot be used to prepare or describe a query
+pub fn is_pseudo_type(type_oid: pg_sys::Oid) -> bool {
+    unsafe { pg_sys::get_typtype(type_oid) as u8 == pg_sys::TYPTYPE_PSEUDO }
+}
+
+// Type name as shown to users in e.g. error messages
+pub fn type_name(type_oid: pg_sys::Oid) -> String {
+    unsafe { std::ffi::CStr::from_ptr(pg_sys::format_type_be(type_oid)) }
+        .to_string_lossy()
+        .into_owned()
 }
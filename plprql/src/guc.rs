@@ -0,0 +1,55 @@
+use pgrx::{GucContext, GucFlags, GucRegistry, GucSetting};
+
+// Number of rows a set-returning function fetches from its portal at a time
+pub(crate) static FETCH_SIZE: GucSetting<i32> = GucSetting::<i32>::new(1000);
+
+// Whether set-returning functions return their results in a tuplestore whenever the caller accepts one
+pub(crate) static MATERIALIZE: GucSetting<bool> = GucSetting::<bool>::new(false);
+
+// Whether the columns of a query's result are matched to the function's result columns by name instead of position
+pub(crate) static MATCH_COLUMNS_BY_NAME: GucSetting<bool> = GucSetting::<bool>::new(false);
+
+// Number of rows of a DO block's result that are reported as notices
+pub(crate) static INLINE_ROWS: GucSetting<i32> = GucSetting::<i32>::new(0);
+
+pub(crate) fn init() {
+    GucRegistry::define_int_guc(
+        c"plprql.fetch_size",
+        c"Number of rows set-returning PL/PRQL functions fetch at a time.",
+        c"Rows are fetched on demand, so a larger value uses more memory per call but fetches less often.",
+        &FETCH_SIZE,
+        1,
+        i32::MAX,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+
+    GucRegistry::define_bool_guc(
+        c"plprql.materialize",
+        c"Return the results of set-returning PL/PRQL functions in a tuplestore when the caller accepts one.",
+        c"By default, rows are returned one at a time, so a caller that stops early does not run the whole query.",
+        &MATERIALIZE,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+
+    GucRegistry::define_bool_guc(
+        c"plprql.match_columns_by_name",
+        c"Match the columns of PL/PRQL query results to the function's result columns by name.",
+        c"By default, columns are matched by position. Can also be set per function with a SET clause.",
+        &MATCH_COLUMNS_BY_NAME,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+
+    GucRegistry::define_int_guc(
+        c"plprql.inline_rows",
+        c"Number of rows of a PL/PRQL DO block's result reported as notices.",
+        c"The number of rows of the result is always reported.",
+        &INLINE_ROWS,
+        0,
+        i32::MAX,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+}
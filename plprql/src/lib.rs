@@ -3,11 +3,24 @@ use pgrx::prelude::*;
 pg_module_magic!();
 
 mod anydatum;
+mod bind;
+mod cache;
+mod cast;
 mod err;
 mod fun;
+mod guc;
+mod params;
 pub mod plprql;
+mod procedure;
 mod spi;
 mod srf;
+mod trigger;
+
+#[pg_guard]
+pub extern "C-unwind" fn _PG_init() {
+    guc::init();
+    cache::init();
+}
 
 /// This module is required by `cargo pgrx tests` invocations.
 /// It must be visible at the root of your extension crate.
@@ -0,0 +1,114 @@
+use crate::err::{PlprqlError, PlprqlResult};
+use crate::fun::type_name;
+use crate::spi::Parameters;
+use pgrx::datum::DatumWithOid;
+use pgrx::prelude::*;
+use pgrx::{JsonB, pg_getarg};
+use serde_json::{Map, Value};
+use std::ffi::CString;
+
+// The PRQL passed to `prql_params(str, ...)` or `prql(str, params)` as its first argument
+pub(crate) unsafe fn query(fcinfo: pg_sys::FunctionCallInfo) -> PlprqlResult<String> {
+    unsafe { pg_getarg::<String>(fcinfo, 0) }.ok_or(PlprqlError::NullQuery)
+}
+
+// Parameters of `prql_params(str, variadic params "any")`, bound as `$1`, `$2`, ... with the types they are passed with.
+// Values passed with `variadic array[...]` arrive as a single array, which is expanded into its elements. String
+// literals have no type of their own when passed to "any", so they are bound as text.
+pub(crate) unsafe fn variadic(fcinfo: pg_sys::FunctionCallInfo) -> Parameters {
+    unsafe {
+        let flinfo = (*fcinfo).flinfo;
+        let args = (*fcinfo).args.as_slice((*fcinfo).nargs as usize);
+
+        let parameters = match pg_sys::get_fn_expr_variadic(flinfo) {
+            true => array_elements(pg_sys::get_fn_expr_argtype(flinfo, 1), args[1]),
+            false => args
+                .iter()
+                .enumerate()
+                .skip(1)
+                .map(|(index, arg)| {
+                    let type_oid = pg_sys::get_fn_expr_argtype(flinfo, index as i32);
+                    match (arg.isnull, type_oid) {
+                        (true, pg_sys::UNKNOWNOID) => DatumWithOid::null_oid(pg_sys::TEXTOID),
+                        (true, _) => DatumWithOid::null_oid(type_oid),
+                        (false, pg_sys::UNKNOWNOID) => DatumWithOid::new(
+                            pg_sys::Datum::from(pg_sys::cstring_to_text(arg.value.cast_mut_ptr())),
+                            pg_sys::TEXTOID,
+                        ),
+                        (false, _) => DatumWithOid::new(arg.value, type_oid),
+                    }
+                })
+                .collect(),
+        };
+
+        Parameters::new(&parameters)
+    }
+}
+
+unsafe fn array_elements(array_type: pg_sys::Oid, array: pg_sys::NullableDatum) -> Vec<DatumWithOid<'static>> {
+    if array.isnull {
+        return Vec::new();
+    }
+
+    unsafe {
+        let element_type = pg_sys::get_element_type(array_type);
+        let mut length = 0;
+        let mut by_value = false;
+        let mut alignment = 0;
+        pg_sys::get_typlenbyvalalign(element_type, &mut length, &mut by_value, &mut alignment);
+
+        let mut values = std::ptr::null_mut();
+        let mut nulls = std::ptr::null_mut();
+        let mut count = 0;
+        pg_sys::deconstruct_array(
+            pg_sys::pg_detoast_datum(array.value.cast_mut_ptr()).cast(),
+            element_type,
+            length as i32,
+            by_value,
+            alignment,
+            &mut values,
+            &mut nulls,
+            &mut count,
+        );
+
+        let values = std::slice::from_raw_parts(values, count as usize);
+        let nulls = std::slice::from_raw_parts(nulls, count as usize);
+        values
+            .iter()
+            .zip(nulls)
+            .map(|(value, is_null)| match is_null {
+                true => DatumWithOid::null_oid(element_type),
+                false => DatumWithOid::new(*value, element_type),
+            })
+            .collect()
+    }
+}
+
+// Parameters of `prql(str, params jsonb)`, which are given as a JSON object and referred to by name, e.g. `$player`
+// for `{"player": "Player1"}`. A null object gives no parameters.
+pub(crate) unsafe fn named(fcinfo: pg_sys::FunctionCallInfo) -> PlprqlResult<Map<String, Value>> {
+    match unsafe { pg_getarg::<JsonB>(fcinfo, 1) } {
+        None => Ok(Map::new()),
+        Some(JsonB(Value::Object(parameters))) => Ok(parameters),
+        Some(_) => Err(PlprqlError::ParametersNotObject),
+    }
+}
+
+// The text a JSON value is bound from, as input for a parameter of the type the query gives it. Strings are unquoted,
+// so they can be input as any type, e.g. dates, while json and jsonb parameters take JSON text as it is. Arrays and
+// objects are only bound to json and jsonb parameters, as their JSON text is not the input of any other type.
+pub(crate) fn json_text(name: &str, value: &Value, type_oid: pg_sys::Oid) -> PlprqlResult<Option<CString>> {
+    let text = match (value, type_oid) {
+        (Value::Null, _) => return Ok(None),
+        (_, pg_sys::JSONOID | pg_sys::JSONBOID) => value.to_string(),
+        (Value::Array(_) | Value::Object(_), _) => {
+            return Err(PlprqlError::NonScalarParameter {
+                name: name.to_string(),
+                expected: type_name(type_oid),
+            });
+        }
+        (Value::String(string), _) => string.clone(),
+        (value, _) => value.to_string(),
+    };
+    Ok(Some(CString::new(text).expect("parameter contained a null byte")))
+}
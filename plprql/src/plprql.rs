@@ -1,32 +1,94 @@
+use crate::bind::bind_arguments;
+use crate::cache;
 use crate::err::{PlprqlError, PlprqlResult};
-use crate::fun::{Function, Return};
-use crate::spi::{fetch_row, fetch_setof, fetch_table};
-use crate::srf::{setof_srf_next, table_srf_next};
+use crate::fun::{Function, Return, is_pseudo_type};
+use crate::guc;
+use crate::params;
+use crate::procedure::{self, Step};
+use crate::spi::{Cursor, describe, fetch_composite, fetch_row, fetch_trigger_row, open_refcursor, run_inline};
+use crate::srf::{setof_srf_materialize, setof_srf_next, table_srf_materialize, table_srf_next, uses_materialize};
+use anstream::adapter::strip_str;
+use pgrx::pg_sys::panic::ErrorReportable;
 use pgrx::prelude::*;
-use prqlc::{DisplayOptions, Options, Target, compile, sql::Dialect};
+use prqlc::{DisplayOptions, ErrorMessage, ErrorMessages, Options, SourceTree, Target, compile, sql::Dialect};
 
 // Allows the user to compile PRQL from SQL
 #[pg_extern]
 pub fn prql_to_sql(prql: &str) -> PlprqlResult<String> {
-    let options = &Options {
+    compile(prql, &options()).map_err(PlprqlError::PrqlError)
+}
+
+// Compile the body of a function, whose arguments can be referred to by name as well as by position
+pub(crate) fn function_to_sql(prql: &str, argument_names: &[Option<String>]) -> PlprqlResult<String> {
+    let sources = SourceTree::from(prql);
+
+    // Errors are reported as `compile` reports them, with their location in the source and without colors
+    let plain = |errors: ErrorMessages| ErrorMessages {
+        inner: errors
+            .composed(&sources)
+            .inner
+            .into_iter()
+            .map(|error| ErrorMessage {
+                display: error.display.map(|display| strip_str(&display).to_string()),
+                ..error
+            })
+            .collect(),
+    };
+
+    let query = prqlc::prql_to_pl_tree(&sources)
+        .and_then(prqlc::pl_to_rq)
+        .map_err(plain)?;
+
+    Ok(prqlc::rq_to_sql(bind_arguments(query, argument_names)?, &options()).map_err(plain)?)
+}
+
+fn options() -> Options {
+    Options {
         format: false,
         target: Target::Sql(Some(Dialect::Postgres)),
         signature_comment: false,
         color: false,
         display: DisplayOptions::Plain,
-    };
+    }
+}
+
+// Allows the user to inspect the cache of compiled PL/PRQL functions, e.g. to confirm hit rates.
+#[pg_extern]
+fn plprql_cache_stats() -> TableIterator<
+    'static,
+    (
+        name!(function_oid, pg_sys::Oid),
+        name!(hits, i64),
+        name!(misses, i64),
+        name!(invalidations, i64),
+        name!(prepares, i64),
+    ),
+> {
+    TableIterator::new(cache::stats())
+}
+
+// Called by PostgreSQL on `do $$ ... $$ language plprql`. Runs the PRQL and reports its result as notices.
+#[pg_extern]
+fn plprql_inline_handler(code_block: pgrx::Internal) -> PlprqlResult<()> {
+    let code_block = unsafe { code_block.get::<pg_sys::InlineCodeBlock>() }.ok_or(PlprqlError::NullInlineCodeBlock)?;
+    let prql = unsafe { std::ffi::CStr::from_ptr(code_block.source_text) }.to_string_lossy();
 
-    compile(prql, options).map_err(PlprqlError::PrqlError)
+    for line in run_inline(&prql_to_sql(&prql)?, guc::INLINE_ROWS.get() as i64)? {
+        pgrx::notice!("{line}");
+    }
+
+    Ok(())
 }
 
 // Allows the user to define PostgreSQL functions with PRQL bodies.
 extension_sql!(
     "create language plprql
     handler plprql_call_handler
+    inline plprql_inline_handler
     validator plprql_call_validator;
     comment on language plprql is 'PRQL procedural language';",
     name = "language_handler",
-    requires = [plprql_call_validator]
+    requires = [plprql_call_validator, plprql_inline_handler]
 );
 
 extension_sql!(
@@ -53,16 +115,76 @@ pub extern "C-unwind" fn plprql_call_handler(fcinfo: pg_sys::FunctionCallInfo) -
 
     unsafe {
         match function.return_mode() {
-            Return::Table => table_srf_next(function.call_info, fetch_table(&function)),
-            Return::SetOf => setof_srf_next(function.call_info, fetch_setof(&function)),
+            Return::Table if uses_materialize(function.call_info) => {
+                table_srf_materialize(function.call_info, || Cursor::open(&function))
+            }
+            Return::SetOf if uses_materialize(function.call_info) => {
+                setof_srf_materialize(function.call_info, || Cursor::open(&function))
+            }
+            Return::Table => table_srf_next(function.call_info, || Cursor::open(&function)),
+            Return::SetOf => setof_srf_next(function.call_info, || Cursor::open(&function)),
+            Return::Composite => fetch_composite(&function),
             Return::Scalar => fetch_row(&function),
+            Return::Cursor => open_refcursor(&function),
+            Return::Trigger => fetch_trigger_row(&function),
+            Return::Procedure => procedure::call(function),
         }
     }
 }
 
+// Called by PostgreSQL on `create function` and `create or replace function`. Rejects bodies that do not compile.
 #[pg_extern]
-fn plprql_call_validator(_function_id: pg_sys::Oid, _function_call_info: pg_sys::FunctionCallInfo) {
-    // TODO
+fn plprql_call_validator(function_oid: pg_sys::Oid, function_call_info: pg_sys::FunctionCallInfo) -> PlprqlResult<()> {
+    let validator_oid = unsafe {
+        function_call_info
+            .as_ref()
+            .ok_or(PlprqlError::NullFunctionCallInfo)?
+            .flinfo
+            .as_ref()
+    }
+    .ok_or(PlprqlError::NullFmgrInfo)?
+    .fn_oid;
+
+    // Bail out if the user is not allowed to validate the function
+    if !unsafe { pg_sys::CheckFunctionValidatorAccess(validator_oid, function_oid) } {
+        return Ok(());
+    }
+
+    // Bail out if the user has disabled validation, e.g. pg_dump output sets check_function_bodies = off
+    if !unsafe { pg_sys::check_function_bodies } {
+        return Ok(());
+    }
+
+    let function = Function::from_oid(function_oid)?;
+    function.check_variadic()?;
+
+    // The last pipeline of a procedure gives its output parameters, unless it is opened as a cursor. The query of a
+    // function returning refcursor can have any shape.
+    let sql = match function.return_mode() {
+        Return::Procedure => procedure::compile(&function)?
+            .into_iter()
+            .rev()
+            .find_map(|step| match step {
+                Step::Query(sql) => Some(sql),
+                _ => None,
+            })
+            .filter(|_| function.output_types() != [pg_sys::REFCURSOROID]),
+        Return::Cursor => function_to_sql(&function.body(), &function.argument_names()).map(|_| None)?,
+        _ => Some(function_to_sql(&function.body(), &function.argument_names())?),
+    };
+
+    // Check the shape of the query's result against the declared return type. Pseudo-typed arguments like
+    // `anyelement` are only resolved at call time, so such queries cannot be described here, nor can the polymorphic
+    // return types that depend on them.
+    let argument_types = function.argument_types();
+    if let Some(sql) = sql
+        && !argument_types.iter().any(|oid| is_pseudo_type(oid.value()))
+        && function.result_columns().is_some()
+    {
+        function.check_result_columns(&describe(&sql, &argument_types)?)?;
+    }
+
+    Ok(())
 }
 
 // Allows user to "select prql('from people | filter planet_id == 1 | sort name') as (name text, age int);".
@@ -89,3 +211,83 @@ extension_sql!(
     $$ language plpgsql;"
     name = "prql_cursor"
 );
+
+// Allows user to "select * from prql_params('from people | filter planet_id == $1 && height > $2', 1, 180) as (...);"
+// with values bound as parameters of the query rather than written into it. Values have the types they are passed
+// with. It is not an overload of `prql`, where a single text value would resolve to `prql(str, cursor_name)` instead.
+extension_sql!(
+    "create function prql_params(str text, variadic params \"any\") returns setof record
+    language C as 'MODULE_PATHNAME', 'prql_with_params';"
+    name = "prql_with_params"
+);
+
+// Allows user to "select * from prql('from people | filter planet_id == $planet', '{\"planet\": 1}'::jsonb) as (...);"
+// with values bound by name as parameters of the query. The type of each parameter is inferred from the query, so
+// e.g. JSON strings can be compared with dates.
+extension_sql!(
+    "create function prql(str text, params jsonb) returns setof record
+    language C as 'MODULE_PATHNAME', 'prql_with_named_params';"
+    name = "prql_with_named_params"
+);
+
+#[unsafe(no_mangle)]
+pub extern "C" fn pg_finfo_prql_with_params() -> &'static pg_sys::Pg_finfo_record {
+    const V1_API: pg_sys::Pg_finfo_record = pg_sys::Pg_finfo_record { api_version: 1 };
+    &V1_API
+}
+
+#[unsafe(no_mangle)]
+#[pg_guard]
+pub extern "C-unwind" fn prql_with_params(fcinfo: pg_sys::FunctionCallInfo) -> pg_sys::Datum {
+    unsafe {
+        return_query(fcinfo, || {
+            let prql = params::query(fcinfo).unwrap_or_report();
+            let sql = function_to_sql(&prql, &[]).unwrap_or_report();
+            Cursor::open_query(&sql, &mut params::variadic(fcinfo))
+        })
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn pg_finfo_prql_with_named_params() -> &'static pg_sys::Pg_finfo_record {
+    const V1_API: pg_sys::Pg_finfo_record = pg_sys::Pg_finfo_record { api_version: 1 };
+    &V1_API
+}
+
+#[unsafe(no_mangle)]
+#[pg_guard]
+pub extern "C-unwind" fn prql_with_named_params(fcinfo: pg_sys::FunctionCallInfo) -> pg_sys::Datum {
+    unsafe {
+        return_query(fcinfo, || {
+            let prql = params::query(fcinfo).unwrap_or_report();
+            let parameters = params::named(fcinfo).unwrap_or_report();
+            let names = parameters.keys().cloned().map(Some).collect::<Vec<_>>();
+            let values = parameters.iter().collect::<Vec<_>>();
+
+            let sql = function_to_sql(&prql, &names)
+                .map_err(|error| match error {
+                    PlprqlError::UndefinedArgument { name } => PlprqlError::UndefinedParameter { name },
+                    error => error,
+                })
+                .unwrap_or_report();
+            Cursor::open_query_inferring_types(&sql, |index, type_oid| {
+                // Positional parameters refer to the keys in their order, and there may be fewer keys than positions
+                let (name, value) = values
+                    .get(index)
+                    .ok_or(PlprqlError::UnnamedParameter { position: index + 1 })
+                    .unwrap_or_report();
+                params::json_text(name, value, type_oid).unwrap_or_report()
+            })
+        })
+    }
+}
+
+// Return the rows of an ad-hoc query as records of the caller's column definition list
+unsafe fn return_query(fcinfo: pg_sys::FunctionCallInfo, open_cursor: impl FnOnce() -> Cursor) -> pg_sys::Datum {
+    unsafe {
+        match uses_materialize(fcinfo) {
+            true => table_srf_materialize(fcinfo, open_cursor),
+            false => table_srf_next(fcinfo, open_cursor),
+        }
+    }
+}
@@ -0,0 +1,218 @@
+use crate::err::{PlprqlError, PlprqlResult};
+use crate::fun::Function;
+use crate::plprql::function_to_sql;
+use crate::spi::{Parameters, column_casts, column_map, convert_tuple, is_passthrough, open_portal};
+use pgrx::pg_sys::panic::ErrorReportable;
+use pgrx::prelude::*;
+use std::ffi::{CStr, CString};
+
+// A procedure's body is a sequence of pipelines, separated by lines that only say `commit` or `rollback`. PRQL has no
+// statements of its own, so transaction control is written between the pipelines rather than in them. Such a line must
+// have blank lines around it, as a line that only says `commit` could also be part of a pipeline, e.g. a column of a
+// tuple spread over several lines.
+pub(crate) enum Step {
+    Query(String),
+    Commit,
+    Rollback,
+}
+
+pub(crate) fn steps(body: &str) -> PlprqlResult<Vec<Step>> {
+    let lines = body.lines().collect::<Vec<_>>();
+    let is_blank = |index: usize| lines.get(index).is_none_or(|line| line.trim().is_empty());
+
+    let mut steps = Vec::new();
+    let mut query = String::new();
+
+    for (index, line) in lines.iter().enumerate() {
+        let control = match line.trim().to_lowercase().as_str() {
+            "commit" => Step::Commit,
+            "rollback" => Step::Rollback,
+            _ => {
+                query.push_str(line);
+                query.push('\n');
+                continue;
+            }
+        };
+
+        if (index > 0 && !is_blank(index - 1)) || !is_blank(index + 1) {
+            return Err(PlprqlError::TransactionControlInPipeline {
+                statement: line.trim().to_string(),
+                line: index + 1,
+            });
+        }
+
+        if !query.trim().is_empty() {
+            steps.push(Step::Query(std::mem::take(&mut query)));
+        }
+        query.clear();
+        steps.push(control);
+    }
+
+    if !query.trim().is_empty() {
+        steps.push(Step::Query(query));
+    }
+
+    Ok(steps)
+}
+
+// Compile the pipelines of a procedure's body to SQL
+pub(crate) fn compile(function: &Function) -> PlprqlResult<Vec<Step>> {
+    let argument_names = function.argument_names();
+    steps(&function.body())?
+        .into_iter()
+        .map(|step| match step {
+            Step::Query(prql) => Ok(Step::Query(function_to_sql(&prql, &argument_names)?)),
+            control => Ok(control),
+        })
+        .collect()
+}
+
+// Run the pipelines of a procedure in order. Earlier pipelines are run for their side effects, e.g. of functions called
+// in s-strings, without keeping their rows, and the last one gives the procedure's output parameters: its first row is returned as their values,
+// or, if the only output parameter is a refcursor, a portal is opened over it under the parameter's name. The SPI
+// connection is non-atomic when the procedure is called with CALL outside of a transaction block, which allows
+// transaction control between pipelines. The function is dropped before the first pipeline runs, as its pg_proc tuple
+// is a syscache reference that must not be held across a commit or rollback.
+pub(crate) fn call(function: Function) -> pg_sys::Datum {
+    let steps = compile(&function).unwrap_or_report();
+    let arguments = function.arguments().unwrap_or_report().unwrap_or_default();
+    let mut parameters = Parameters::new(&arguments);
+
+    let call_info = function.call_info;
+    let output_types = function.output_types();
+    let cursor_name = parameters.cursor_name(&function);
+    let match_columns_by_name = function.match_columns_by_name();
+    let is_atomic = function.is_atomic();
+    drop(function);
+
+    let last_query = steps.iter().rposition(|step| matches!(step, Step::Query(_)));
+    let mut result_desc: pg_sys::TupleDesc = std::ptr::null_mut();
+    if !output_types.is_empty() {
+        unsafe { pg_sys::get_call_result_type(call_info, std::ptr::null_mut(), &mut result_desc) };
+    }
+
+    unsafe {
+        let options = match is_atomic {
+            true => 0,
+            false => pg_sys::SPI_OPT_NONATOMIC as i32,
+        };
+        Spi::check_status(pg_sys::SPI_connect_ext(options)).unwrap_or_report();
+
+        let mut result = None;
+        for (index, step) in steps.iter().enumerate() {
+            match step {
+                Step::Query(sql) if Some(index) == last_query && !result_desc.is_null() => {
+                    let sql = CString::new(sql.as_str()).expect("query contained a null byte");
+                    result = Some(match output_types.as_slice() {
+                        [pg_sys::REFCURSOROID] => {
+                            open_cursor(&sql, cursor_name.as_deref(), &mut parameters, result_desc)
+                        }
+                        _ => fetch_output(&sql, &mut parameters, result_desc, match_columns_by_name),
+                    });
+                }
+                Step::Query(sql) => {
+                    let sql = CString::new(sql.as_str()).expect("query contained a null byte");
+                    run_to_end(&sql, &mut parameters);
+                }
+                Step::Commit => pg_sys::SPI_commit(),
+                Step::Rollback => pg_sys::SPI_rollback(),
+            }
+        }
+
+        // Output parameters are null if the body has no pipeline to give them
+        let datum = match result {
+            Some(datum) => datum,
+            None if !result_desc.is_null() => null_output(result_desc),
+            None => pg_sys::Datum::from(0),
+        };
+
+        pg_sys::SPI_finish();
+        datum
+    }
+}
+
+// Run a pipeline for its side effects. Its rows are skipped by moving a portal over it to the end, so that they are
+// not collected into a tuple table.
+unsafe fn run_to_end(sql: &CStr, parameters: &mut Parameters) {
+    unsafe {
+        let portal_name = open_portal(sql, None, parameters);
+        let portal = pg_sys::SPI_cursor_find(portal_name.as_ptr());
+        pg_sys::SPI_cursor_move(portal, true, i64::MAX);
+        pg_sys::SPI_cursor_close(portal);
+    }
+}
+
+unsafe fn execute(sql: &CStr, parameters: &mut Parameters, count: i64) -> PlprqlResult<()> {
+    let status = unsafe {
+        pg_sys::SPI_execute_with_args(
+            sql.as_ptr(),
+            parameters.types.len() as i32,
+            parameters.types.as_mut_ptr(),
+            parameters.values.as_mut_ptr(),
+            parameters.nulls.as_ptr(),
+            false,
+            count,
+        )
+    };
+    Spi::check_status(status)?;
+    Ok(())
+}
+
+// Return the first row of the last pipeline as the values of the output parameters, which are all null if there is no row
+unsafe fn fetch_output(
+    sql: &CStr,
+    parameters: &mut Parameters,
+    target: pg_sys::TupleDesc,
+    match_columns_by_name: bool,
+) -> pg_sys::Datum {
+    unsafe {
+        execute(sql, parameters, 1).unwrap_or_report();
+        if pg_sys::SPI_processed == 0 {
+            return null_output(target);
+        }
+
+        let tuptable = pg_sys::SPI_tuptable;
+        let source = (*tuptable).tupdesc;
+        let tuple = *(*tuptable).vals;
+
+        let column_map = column_map(source, target, match_columns_by_name).unwrap_or_report();
+        let tuple = match is_passthrough(source, target, &column_map) {
+            true => tuple,
+            false => {
+                let casts = column_casts(source, target, &column_map).unwrap_or_report();
+                convert_tuple(tuple, source, target, &column_map, &casts)
+            }
+        };
+
+        pg_sys::Datum::from(pg_sys::SPI_returntuple(tuple, target))
+    }
+}
+
+// Open a portal over the last pipeline under the name given by the refcursor parameter, or a generated name if it is
+// null, and return the name as the parameter's value
+unsafe fn open_cursor(
+    sql: &CStr,
+    name: Option<&CStr>,
+    parameters: &mut Parameters,
+    target: pg_sys::TupleDesc,
+) -> pg_sys::Datum {
+    unsafe {
+        let portal_name = open_portal(sql, name, parameters);
+
+        let mut values = [pg_sys::Datum::from(pg_sys::cstring_to_text(portal_name.as_ptr()))];
+        let mut nulls = [false];
+        let tuple = pg_sys::heap_form_tuple(target, values.as_mut_ptr(), nulls.as_mut_ptr());
+        pg_sys::Datum::from(pg_sys::SPI_returntuple(tuple, target))
+    }
+}
+
+// Output parameters that are all null. CALL expects a row even if the procedure has no values to give.
+unsafe fn null_output(target: pg_sys::TupleDesc) -> pg_sys::Datum {
+    unsafe {
+        let natts = (*target).natts as usize;
+        let mut values = vec![pg_sys::Datum::from(0); natts];
+        let mut nulls = vec![true; natts];
+        let tuple = pg_sys::heap_form_tuple(target, values.as_mut_ptr(), nulls.as_mut_ptr());
+        pg_sys::Datum::from(pg_sys::SPI_returntuple(tuple, target))
+    }
+}
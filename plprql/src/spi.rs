@@ -1,117 +1,679 @@
 use crate::anydatum::AnyDatum;
-use crate::fun::Function;
-use crate::plprql::prql_to_sql;
+use crate::cache;
+use crate::cast::Cast;
+use crate::err::PlprqlResult;
+use crate::fun::{Function, is_pseudo_type, match_columns};
+use crate::guc;
+use pgrx::datum::DatumWithOid;
 use pgrx::pg_return_null;
 use pgrx::pg_sys::panic::ErrorReportable;
 use pgrx::prelude::*;
-use pgrx::{IntoDatum, IntoHeapTuple, pg_sys};
+use pgrx::{IntoDatum, PgTupleDesc, pg_sys};
+use std::ffi::{CStr, CString, c_char, c_int, c_void};
+use std::ptr::NonNull;
 
-pub struct Row {
-    pub datums: Vec<Option<AnyDatum>>,
+#[cfg(any(feature = "pg13", feature = "pg14"))]
+unsafe extern "C-unwind" {
+    // Declared in parser/parse_param.h which pgrx does not generate bindings for
+    #[link_name = "parse_variable_parameters"]
+    fn setup_parse_variable_parameters(
+        pstate: *mut pg_sys::ParseState,
+        paramTypes: *mut *mut pg_sys::Oid,
+        numParams: *mut c_int,
+    );
 }
 
-impl Clone for Row {
-    fn clone(&self) -> Self {
-        Row {
-            datums: self.datums.clone(),
+#[cfg(not(any(feature = "pg13", feature = "pg14")))]
+unsafe extern "C-unwind" {
+    // Declared in parser/parse_param.h which pgrx does not generate bindings for
+    fn setup_parse_variable_parameters(
+        pstate: *mut pg_sys::ParseState,
+        paramTypes: *mut *mut pg_sys::Oid,
+        numParams: *mut c_int,
+    );
+}
+
+// Prepare a query without executing it and return the name and type of its result columns
+pub(crate) fn describe(sql: &str, argument_types: &[PgOid]) -> PlprqlResult<Vec<(String, pg_sys::Oid)>> {
+    let sql = CString::new(sql).expect("query contained a null byte");
+    let mut argument_types = argument_types.iter().map(|oid| oid.value()).collect::<Vec<_>>();
+
+    Spi::connect(|_| unsafe {
+        let Some(plan) = NonNull::new(pg_sys::SPI_prepare(
+            sql.as_ptr(),
+            argument_types.len() as i32,
+            argument_types.as_mut_ptr(),
+        )) else {
+            // Syntax and analysis errors are raised by PostgreSQL, SPI errors are reported through SPI_result
+            Spi::check_status(pg_sys::SPI_result)?;
+            return Ok(vec![]);
+        };
+
+        // PRQL compiles to a single statement, so there is exactly one plan source
+        let plan_sources = pg_sys::SPI_plan_get_plan_sources(plan.as_ptr());
+        if plan_sources.is_null() || (*plan_sources).length == 0 {
+            return Ok(vec![]);
         }
-    }
+
+        let plan_source = (*(*plan_sources).elements).ptr_value as *mut pg_sys::CachedPlanSource;
+        if (*plan_source).resultDesc.is_null() {
+            return Ok(vec![]);
+        }
+
+        let tupdesc = PgTupleDesc::from_pg_unchecked((*plan_source).resultDesc);
+        Ok(tupdesc
+            .iter()
+            .filter(|attribute| !attribute.is_dropped())
+            .map(|attribute| (attribute.name().to_string(), attribute.type_oid().value()))
+            .collect())
+    })
 }
 
-impl IntoHeapTuple for Row {
-    unsafe fn into_heap_tuple(self, tupdesc: *mut pg_sys::TupleDescData) -> *mut pg_sys::HeapTupleData {
-        let mut datums = Vec::with_capacity(self.datums.len());
-        let mut is_nulls = Vec::with_capacity(self.datums.len());
+// A portal over the result of a function. The portal outlives the SPI connection it was opened in, so a
+// set-returning function can fetch rows on demand across calls and stop early when the caller has seen enough.
+pub struct Cursor {
+    name: Option<CString>,
+    match_columns_by_name: bool,
+}
 
-        for any_datum in self.datums.into_iter() {
-            match any_datum.into_datum() {
-                Some(datum) => {
-                    datums.push(datum);
-                    is_nulls.push(false);
-                }
-                None => {
-                    datums.push(pg_sys::Datum::from(0));
-                    is_nulls.push(true);
-                }
-            };
+impl Cursor {
+    pub(crate) fn open(function: &Function) -> Self {
+        let arguments = function.arguments().unwrap_or_report().unwrap_or_default();
+        let mut parameters = Parameters::new(&arguments);
+
+        Cursor {
+            name: Some(open_plan(function, &mut parameters, None)),
+            match_columns_by_name: function.match_columns_by_name(),
         }
+    }
 
-        unsafe { pg_sys::heap_form_tuple(tupdesc, datums.as_mut_ptr(), is_nulls.as_mut_ptr()) }
+    // Open a portal over an ad-hoc query, e.g. of `prql_params(str, ...)`, with parameters of known types
+    pub(crate) fn open_query(sql: &str, parameters: &mut Parameters) -> Self {
+        let sql = CString::new(sql).expect("query contained a null byte");
+        let name = Spi::connect(|_| unsafe { open_portal(&sql, None, parameters) });
+        Cursor::from_portal(name)
     }
-}
 
-pub(crate) fn fetch_table(function: &Function) -> impl FnOnce() -> Option<Vec<Row>> + '_ {
-    || -> Option<Vec<Row>> {
-        let sql = prql_to_sql(&function.body()).unwrap_or_report();
-        let arguments = function.arguments().unwrap_or_report();
-
-        Spi::connect(|client| {
-            let rows = client
-                .select(&sql, None, arguments.as_deref().unwrap_or(&[]))
-                .unwrap_or_report()
-                .map(|heap_tuple| Row {
-                    datums: (0..heap_tuple.columns())
-                        .map(|i| {
-                            heap_tuple
-                                // Ordinals are 1-indexed
-                                .get_datum_by_ordinal(i + 1)
-                                .unwrap_or_report()
-                                .value::<AnyDatum>()
-                                .unwrap_or_report()
-                        })
-                        .collect::<Vec<Option<AnyDatum>>>(),
-                })
-                .collect::<Vec<Row>>();
+    // Open a portal over an ad-hoc query whose parameters get their types from how the query uses them, like those of
+    // PREPARE without a list of types. `value` gives the text of each parameter's value as input for its type, or
+    // None for null.
+    pub(crate) fn open_query_inferring_types(sql: &str, value: impl Fn(usize, pg_sys::Oid) -> Option<CString>) -> Self {
+        let sql = CString::new(sql).expect("query contained a null byte");
+        let mut inferred = InferredTypes {
+            types: std::ptr::null_mut(),
+            count: 0,
+        };
+
+        let name = Spi::connect(|_| unsafe {
+            let Some(plan) = NonNull::new(pg_sys::SPI_prepare_params(
+                sql.as_ptr(),
+                Some(infer_parameter_types),
+                (&raw mut inferred).cast(),
+                0,
+            )) else {
+                Spi::check_status(pg_sys::SPI_result).unwrap_or_report();
+                unreachable!("SPI_prepare_params failed without an error");
+            };
+
+            // The plan has no parameter types of its own, so the parameters are passed as a list with their types.
+            // Parameters the query does not use have no type and are passed as null.
+            let count = inferred.count as usize;
+            let parameters = pg_sys::makeParamList(inferred.count);
+            for (index, parameter) in (*parameters).params.as_mut_slice(count).iter_mut().enumerate() {
+                let type_oid = *inferred.types.add(index);
+                let text = match type_oid {
+                    pg_sys::InvalidOid | pg_sys::UNKNOWNOID => None,
+                    _ => value(index, type_oid),
+                };
+
+                *parameter = pg_sys::ParamExternData {
+                    value: text
+                        .as_deref()
+                        .map_or(pg_sys::Datum::from(0), |text| input(text, type_oid)),
+                    isnull: text.is_none(),
+                    pflags: pg_sys::PARAM_FLAG_CONST as u16,
+                    ptype: type_oid,
+                };
+            }
+
+            let portal = pg_sys::SPI_cursor_open_with_paramlist(std::ptr::null(), plan.as_ptr(), parameters, false);
+            CStr::from_ptr((*portal).name).to_owned()
+        });
+
+        Cursor::from_portal(name)
+    }
+
+    // Ad-hoc queries match their columns to the caller's column definition list as the session says
+    fn from_portal(name: CString) -> Self {
+        Cursor {
+            name: Some(name),
+            match_columns_by_name: guc::MATCH_COLUMNS_BY_NAME.get(),
+        }
+    }
+
+    // Fetch the next batch of rows and pass each row to `put` as a tuple of the caller's row type, closing the portal
+    // once it is exhausted. Tuples are passed through as SPI returns them when their columns already have the
+    // caller's types, and only cast when they do not. Tuples live in SPI memory, so `put` must copy what it keeps.
+    pub(crate) fn fetch(&mut self, count: i64, tuple_desc: pg_sys::TupleDesc, mut put: impl FnMut(pg_sys::HeapTuple)) {
+        let Some(name) = self.name.take() else {
+            return;
+        };
+
+        Spi::connect(|_| unsafe {
+            let portal = pg_sys::SPI_cursor_find(name.as_ptr());
+            pg_sys::SPI_cursor_fetch(portal, true, count);
+
+            let tuptable = pg_sys::SPI_tuptable;
+            let processed = pg_sys::SPI_processed as usize;
+
+            if processed > 0 {
+                let source = (*tuptable).tupdesc;
+                let tuples = std::slice::from_raw_parts((*tuptable).vals, processed);
 
-            if rows.is_empty() {
-                return None;
+                let column_map = column_map(source, tuple_desc, self.match_columns_by_name).unwrap_or_report();
+
+                match is_passthrough(source, tuple_desc, &column_map) {
+                    true => tuples.iter().for_each(|tuple| put(*tuple)),
+                    false => {
+                        let casts = column_casts(source, tuple_desc, &column_map).unwrap_or_report();
+                        tuples
+                            .iter()
+                            .for_each(|tuple| put(convert_tuple(*tuple, source, tuple_desc, &column_map, &casts)))
+                    }
+                }
             }
 
-            Some(rows)
+            // Keep the portal open for the next call unless it has run dry
+            match processed as i64 == count {
+                true => self.name = Some(name),
+                false => pg_sys::SPI_cursor_close(portal),
+            }
         })
     }
+
+    pub(crate) fn is_exhausted(&self) -> bool {
+        self.name.is_none()
+    }
 }
 
-pub(crate) fn fetch_setof(function: &Function) -> impl FnOnce() -> Option<Vec<Option<AnyDatum>>> + '_ {
-    || -> Option<Vec<Option<AnyDatum>>> {
-        let sql = prql_to_sql(&function.body()).unwrap_or_report();
-        let arguments = function.arguments().unwrap_or_report();
-
-        Spi::connect(|client| {
-            let column = client
-                .select(&sql, None, arguments.as_deref().unwrap_or(&[]))
-                .unwrap_or_report()
-                .map(|heap_tuple| {
-                    heap_tuple
-                        // Ordinals are 1-indexed
-                        .get_datum_by_ordinal(1)
-                        .unwrap_or_report()
-                        .value::<AnyDatum>()
-                        .unwrap_or_report()
-                })
-                .collect::<Vec<Option<AnyDatum>>>();
+impl Drop for Cursor {
+    // Close the portal if the caller stops fetching before the result is exhausted
+    fn drop(&mut self) {
+        let Some(name) = self.name.take() else {
+            return;
+        };
 
-            if column.is_empty() {
-                return None;
+        unsafe {
+            // The portal is already gone if the transaction has been aborted
+            let portal = pg_sys::SPI_cursor_find(name.as_ptr());
+            if !portal.is_null() {
+                pg_sys::SPI_cursor_close(portal);
             }
+        }
+    }
+}
+
+// Whether values of one type can be used as values of another without conversion
+fn is_same_representation(source: pg_sys::Oid, target: pg_sys::Oid) -> bool {
+    source == target || unsafe { pg_sys::IsBinaryCoercible(source, target) }
+}
+
+// Match the columns of SPI result tuples to the columns of the caller's row type, ignoring dropped columns, and
+// return the index of the result column for each of the caller's columns
+pub(crate) fn column_map(
+    source: pg_sys::TupleDesc,
+    target: pg_sys::TupleDesc,
+    by_name: bool,
+) -> PlprqlResult<Vec<usize>> {
+    let source = unsafe { PgTupleDesc::from_pg_unchecked(source) };
+    let target = unsafe { PgTupleDesc::from_pg_unchecked(target) };
+
+    let source_names = source.iter().map(|attribute| attribute.name()).collect::<Vec<_>>();
+    let target_names = target
+        .iter()
+        .filter(|attribute| !attribute.is_dropped())
+        .map(|attribute| attribute.name())
+        .collect::<Vec<_>>();
 
-            Some(column)
+    match_columns(&target_names, &source_names, by_name)
+}
+
+// Whether SPI result tuples can be handed to the caller as they are. This requires the result to have the caller's
+// columns in the same order, with no dropped columns in between, and column types with the same representation.
+pub(crate) fn is_passthrough(source: pg_sys::TupleDesc, target: pg_sys::TupleDesc, column_map: &[usize]) -> bool {
+    let source = unsafe { PgTupleDesc::from_pg_unchecked(source) };
+    let target = unsafe { PgTupleDesc::from_pg_unchecked(target) };
+
+    target.len() == column_map.len()
+        && column_map.iter().enumerate().all(|(i, j)| i == *j)
+        && source
+            .iter()
+            .zip(target.iter())
+            .all(|(source, target)| is_same_representation(source.type_oid().value(), target.type_oid().value()))
+}
+
+// Casts of the columns of SPI result tuples to the columns of the caller's row type. Columns with the same
+// representation need no cast.
+pub(crate) fn column_casts(
+    source: pg_sys::TupleDesc,
+    target: pg_sys::TupleDesc,
+    column_map: &[usize],
+) -> PlprqlResult<Vec<Option<Cast>>> {
+    let source = unsafe { PgTupleDesc::from_pg_unchecked(source) };
+    let target = unsafe { PgTupleDesc::from_pg_unchecked(target) };
+
+    target
+        .iter()
+        .filter(|attribute| !attribute.is_dropped())
+        .zip(column_map)
+        .enumerate()
+        .map(|(position, (target, j))| {
+            let source = source.get(*j).expect("column map should point to a result column");
+            match is_same_representation(source.type_oid().value(), target.type_oid().value()) {
+                true => Ok(None),
+                false => Cast::new(
+                    position + 1,
+                    source.type_oid().value(),
+                    source.atttypmod,
+                    target.type_oid().value(),
+                    target.atttypmod,
+                )
+                .map(Some),
+            }
         })
+        .collect()
+}
+
+// Form a tuple of the caller's row type from an SPI result tuple, taking columns in the order of the column map and
+// casting the columns that need it
+pub(crate) unsafe fn convert_tuple(
+    tuple: pg_sys::HeapTuple,
+    source: pg_sys::TupleDesc,
+    target: pg_sys::TupleDesc,
+    column_map: &[usize],
+    casts: &[Option<Cast>],
+) -> pg_sys::HeapTuple {
+    unsafe {
+        let source_len = (*source).natts as usize;
+        let mut source_datums = vec![pg_sys::Datum::from(0); source_len];
+        let mut source_nulls = vec![true; source_len];
+        pg_sys::heap_deform_tuple(tuple, source, source_datums.as_mut_ptr(), source_nulls.as_mut_ptr());
+
+        let target = PgTupleDesc::from_pg_unchecked(target);
+        let mut datums = vec![pg_sys::Datum::from(0); target.len()];
+        let mut nulls = vec![true; target.len()];
+
+        // Dropped columns of the caller's row type stay null
+        let columns = target
+            .iter()
+            .enumerate()
+            .filter(|(_, attribute)| !attribute.is_dropped())
+            .map(|(i, _)| i);
+        for ((i, j), cast) in columns.zip(column_map).zip(casts) {
+            let datum = (!source_nulls[*j]).then_some(source_datums[*j]);
+            let datum = match cast {
+                Some(cast) => cast.apply(datum),
+                None => datum,
+            };
+
+            if let Some(datum) = datum {
+                datums[i] = datum;
+                nulls[i] = false;
+            }
+        }
+
+        pg_sys::heap_form_tuple(target.as_ptr(), datums.as_mut_ptr(), nulls.as_mut_ptr())
     }
 }
 
 pub(crate) fn fetch_row(function: &Function) -> pg_sys::Datum {
-    let sql = prql_to_sql(&function.body()).unwrap_or_report();
-    let arguments = function.arguments().unwrap_or_report();
+    let arguments = function.arguments().unwrap_or_report().unwrap_or_default();
+    let mut parameters = Parameters::new(&arguments);
 
-    Spi::connect(|client| {
-        client
-            .select(&sql, None, arguments.as_deref().unwrap_or(&[]))
-            .unwrap_or_report()
-            .first()
-            .get_one::<AnyDatum>()
+    let (value, source_type) = Spi::connect(|_| unsafe {
+        execute_plan(function, &mut parameters, 1);
+
+        let tuptable = pg_sys::SPI_tuptable;
+        let source = (*tuptable).tupdesc;
+        let source_type = pg_sys::SPI_gettypeid(source, 1);
+        if pg_sys::SPI_processed == 0 {
+            return (None, source_type);
+        }
+
+        let mut is_null = false;
+        let datum = pg_sys::SPI_getbinval(*(*tuptable).vals, source, 1, &mut is_null);
+        (
+            AnyDatum::from_polymorphic_datum(datum, is_null, source_type),
+            source_type,
+        )
+    });
+
+    // Convert after disconnecting from SPI, so the datum is allocated in the function's memory context rather than
+    // in SPI's, which is freed on disconnect
+    let datum = value.and_then(|value| value.into_datum());
+
+    let return_type = function.return_type();
+    let datum = match is_same_representation(source_type, return_type) || is_pseudo_type(return_type) {
+        true => datum,
+        false => Cast::new(1, source_type, -1, return_type, -1)
             .unwrap_or_report()
-            .into_datum()
+            .apply(datum),
+    };
+
+    datum.unwrap_or_else(|| unsafe { pg_return_null(function.call_info) })
+}
+
+// Return the first row of a function returning a single composite value, e.g. `returns people` or a function with OUT
+// parameters, as a composite datum of the caller's row type. The query can also return the composite value as a
+// whole. Null if the query returns no rows.
+pub(crate) fn fetch_composite(function: &Function) -> pg_sys::Datum {
+    let arguments = function.arguments().unwrap_or_report().unwrap_or_default();
+    let mut parameters = Parameters::new(&arguments);
+
+    // The caller's row type is unknown when an anonymous record is returned without a column definition list, in
+    // which case the row type is that of the query's result
+    let mut result_desc: pg_sys::TupleDesc = std::ptr::null_mut();
+    unsafe { pg_sys::get_call_result_type(function.call_info, std::ptr::null_mut(), &mut result_desc) };
+
+    let datum = Spi::connect(|_| unsafe {
+        execute_plan(function, &mut parameters, 1);
+        if pg_sys::SPI_processed == 0 {
+            return None;
+        }
+
+        let tuptable = pg_sys::SPI_tuptable;
+        let source = (*tuptable).tupdesc;
+        let tuple = *(*tuptable).vals;
+
+        let source_types = PgTupleDesc::from_pg_unchecked(source)
+            .iter()
+            .map(|attribute| attribute.type_oid().value())
+            .collect::<Vec<_>>();
+
+        // Values are copied into the function's memory context, as SPI's is freed on disconnect
+        if function.is_whole_row(&source_types) {
+            let mut is_null = false;
+            let value = pg_sys::SPI_getbinval(tuple, source, 1, &mut is_null);
+            return (!is_null).then(|| pg_sys::SPI_datumTransfer(value, false, -1));
+        }
+
+        let target = match result_desc.is_null() {
+            true => source,
+            false => result_desc,
+        };
+
+        let column_map = column_map(source, target, function.match_columns_by_name()).unwrap_or_report();
+        let tuple = match is_passthrough(source, target, &column_map) {
+            true => tuple,
+            false => {
+                let casts = column_casts(source, target, &column_map).unwrap_or_report();
+                convert_tuple(tuple, source, target, &column_map, &casts)
+            }
+        };
+
+        Some(pg_sys::Datum::from(pg_sys::SPI_returntuple(tuple, target)))
+    });
+
+    datum.unwrap_or_else(|| unsafe { pg_return_null(function.call_info) })
+}
+
+// Return the row a trigger stores, i.e. the first row of the query's result as a row of the trigger's table, or null to
+// skip the operation if the query returns no rows. Triggers that fire for each statement run the query but always
+// return null, as PostgreSQL rejects any other result of a statement-level trigger.
+pub(crate) fn fetch_trigger_row(function: &Function) -> pg_sys::Datum {
+    let Some(trigger_data) = function.trigger_data() else {
+        pgrx::error!("trigger functions can only be called as triggers");
+    };
+
+    let arguments = function.arguments().unwrap_or_report().unwrap_or_default();
+    let mut parameters = Parameters::new(&arguments);
+    let target = unsafe { (*(*trigger_data).tg_relation).rd_att };
+    let for_row = unsafe { (*trigger_data).tg_event & pg_sys::TRIGGER_EVENT_ROW != 0 };
+
+    let tuple = Spi::connect(|_| unsafe {
+        execute_plan(function, &mut parameters, 1);
+        if pg_sys::SPI_processed == 0 || !for_row {
+            return std::ptr::null_mut();
+        }
+
+        let tuptable = pg_sys::SPI_tuptable;
+        let source = (*tuptable).tupdesc;
+        let tuple = *(*tuptable).vals;
+
+        let column_map = column_map(source, target, function.match_columns_by_name()).unwrap_or_report();
+        let tuple = match is_passthrough(source, target, &column_map) {
+            true => tuple,
+            false => {
+                let casts = column_casts(source, target, &column_map).unwrap_or_report();
+                convert_tuple(tuple, source, target, &column_map, &casts)
+            }
+        };
+
+        // Copy the tuple into the function's memory context, as SPI's is freed on disconnect
+        pg_sys::SPI_copytuple(tuple)
+    });
+
+    pg_sys::Datum::from(tuple)
+}
+
+// Arguments in the form SPI takes them for queries that are not prepared in advance
+pub(crate) struct Parameters {
+    pub(crate) types: Vec<pg_sys::Oid>,
+    pub(crate) values: Vec<pg_sys::Datum>,
+    pub(crate) nulls: Vec<c_char>,
+}
+
+impl Parameters {
+    pub(crate) fn new(arguments: &[DatumWithOid<'static>]) -> Self {
+        Parameters {
+            types: arguments.iter().map(|argument| argument.oid()).collect(),
+            values: arguments
+                .iter()
+                .map(|argument| {
+                    argument
+                        .datum()
+                        .map_or(pg_sys::Datum::from(0), |datum| datum.sans_lifetime())
+                })
+                .collect(),
+            nulls: arguments
+                .iter()
+                .map(|argument| match argument.datum() {
+                    Some(_) => b' ' as c_char,
+                    None => b'n' as c_char,
+                })
+                .collect(),
+        }
+    }
+
+    // The cursor name given as the value of the function's refcursor argument, if it is not null
+    pub(crate) fn cursor_name(&self, function: &Function) -> Option<CString> {
+        let index = function
+            .cursor_argument()
+            .filter(|index| self.nulls[*index] == b' ' as c_char)?;
+        let name = unsafe { pg_sys::text_to_cstring(self.values[index].cast_mut_ptr()) };
+        Some(unsafe { CStr::from_ptr(name) }.to_owned())
+    }
+}
+
+// Types of the parameters of a query, as the parser infers them while the query is prepared
+struct InferredTypes {
+    types: *mut pg_sys::Oid,
+    count: c_int,
+}
+
+#[pg_guard]
+unsafe extern "C-unwind" fn infer_parameter_types(pstate: *mut pg_sys::ParseState, arg: *mut c_void) {
+    let inferred = arg as *mut InferredTypes;
+    unsafe { setup_parse_variable_parameters(pstate, &raw mut (*inferred).types, &raw mut (*inferred).count) };
+}
+
+// Convert text to a value of a type with the type's input function
+unsafe fn input(text: &CStr, type_oid: pg_sys::Oid) -> pg_sys::Datum {
+    unsafe {
+        let mut input_function = pg_sys::InvalidOid;
+        let mut io_parameter = pg_sys::InvalidOid;
+        pg_sys::getTypeInputInfo(type_oid, &mut input_function, &mut io_parameter);
+        pg_sys::OidInputFunctionCall(input_function, text.as_ptr().cast_mut(), io_parameter, -1)
+    }
+}
+
+// Run the saved plan of a function with the function's arguments as its parameters, leaving at most `count` rows of the
+// result in SPI_tuptable. SPI must be connected.
+unsafe fn execute_plan(function: &Function, parameters: &mut Parameters, count: i64) {
+    let plan = cache::prepare(function).unwrap_or_report();
+    let status = unsafe {
+        pg_sys::SPI_execute_plan(
+            plan.as_ptr(),
+            parameters.values.as_mut_ptr(),
+            parameters.nulls.as_ptr(),
+            false,
+            count,
+        )
+    };
+    Spi::check_status(status).unwrap_or_report();
+}
+
+// Open a portal over the saved plan of a function with the function's arguments as its parameters, under the given name
+// or a generated one, and return the portal's name. The portal outlives the SPI connection it is opened on.
+fn open_plan(function: &Function, parameters: &mut Parameters, name: Option<&CStr>) -> CString {
+    Spi::connect(|_| unsafe {
+        let plan = cache::prepare(function).unwrap_or_report();
+        let portal = pg_sys::SPI_cursor_open(
+            name.map_or(std::ptr::null(), CStr::as_ptr),
+            plan.as_ptr(),
+            parameters.values.as_mut_ptr(),
+            parameters.nulls.as_ptr(),
+            false,
+        );
+        CStr::from_ptr((*portal).name).to_owned()
     })
-    .unwrap_or_else(|| unsafe { pg_return_null(function.call_info) })
+}
+
+// Open a portal over a query under the given name, or a generated name if there is none, and return the portal's name.
+// The portal outlives the SPI connection, until it is closed or the transaction ends.
+pub(crate) unsafe fn open_portal(sql: &CStr, name: Option<&CStr>, parameters: &mut Parameters) -> CString {
+    unsafe {
+        let portal = pg_sys::SPI_cursor_open_with_args(
+            name.map_or(std::ptr::null(), CStr::as_ptr),
+            sql.as_ptr(),
+            parameters.types.len() as i32,
+            parameters.types.as_mut_ptr(),
+            parameters.values.as_mut_ptr(),
+            parameters.nulls.as_ptr(),
+            false,
+            0,
+        );
+        CStr::from_ptr((*portal).name).to_owned()
+    }
+}
+
+// Open a portal over the query of a function that returns refcursor and return its name, so the caller can fetch the
+// result in pages. The portal is named by the function's refcursor argument, or gets a generated name if there is none.
+pub(crate) fn open_refcursor(function: &Function) -> pg_sys::Datum {
+    let arguments = function.arguments().unwrap_or_report().unwrap_or_default();
+    let mut parameters = Parameters::new(&arguments);
+    let name = parameters.cursor_name(function);
+
+    let portal_name = open_plan(function, &mut parameters, name.as_deref());
+
+    // The name is converted after SPI is disconnected, so that it is allocated in the caller's memory context
+    pg_sys::Datum::from(unsafe { pg_sys::cstring_to_text(portal_name.as_ptr()) })
+}
+
+// Run the query of a DO block and give the lines that report its result: the columns and the first rows, followed by
+// the number of rows. The rest of the result is counted by moving the portal to its end, so it is not kept in memory.
+pub(crate) fn run_inline(sql: &str, rows_to_report: i64) -> PlprqlResult<Vec<String>> {
+    let sql = CString::new(sql).expect("query contained a null byte");
+
+    Spi::connect(|_| unsafe {
+        let Some(plan) = NonNull::new(pg_sys::SPI_prepare(sql.as_ptr(), 0, std::ptr::null_mut())) else {
+            Spi::check_status(pg_sys::SPI_result)?;
+            return Ok(vec![]);
+        };
+
+        let portal = pg_sys::SPI_cursor_open(
+            std::ptr::null(),
+            plan.as_ptr(),
+            std::ptr::null_mut(),
+            std::ptr::null(),
+            false,
+        );
+        let mut row_count = 0;
+        let mut report = vec![];
+
+        if rows_to_report > 0 {
+            pg_sys::SPI_cursor_fetch(portal, true, rows_to_report);
+            let tuptable = pg_sys::SPI_tuptable;
+            let tuple_desc = (*tuptable).tupdesc;
+            let tuples = std::slice::from_raw_parts((*tuptable).vals, pg_sys::SPI_processed as usize);
+            row_count += tuples.len() as u64;
+
+            // The columns are only reported above rows, so an empty result is reported by its row count alone
+            if !tuples.is_empty() {
+                let columns = PgTupleDesc::from_pg_unchecked(tuple_desc)
+                    .iter()
+                    .filter(|attribute| !attribute.is_dropped())
+                    .map(|attribute| attribute.name().to_string())
+                    .collect::<Vec<_>>();
+                report.push(columns.join(" | "));
+            }
+
+            for tuple in tuples {
+                let values = (1..=(*tuple_desc).natts)
+                    .map(|column| {
+                        let value = pg_sys::SPI_getvalue(*tuple, tuple_desc, column);
+                        match value.is_null() {
+                            true => String::new(),
+                            false => std::ffi::CStr::from_ptr(value).to_string_lossy().into_owned(),
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                report.push(values.join(" | "));
+            }
+        }
+
+        pg_sys::SPI_cursor_move(portal, true, i64::MAX);
+        row_count += pg_sys::SPI_processed;
+        pg_sys::SPI_cursor_close(portal);
+
+        report.push(match row_count {
+            1 => "1 row".to_string(),
+            _ => format!("{row_count} rows"),
+        });
+
+        Ok(report)
+    })
+}
+
+#[cfg(any(test, feature = "pg_test"))]
+#[pgrx::pg_schema]
+mod tests {
+    use super::run_inline;
+    use pgrx::prelude::*;
+
+    #[pg_test]
+    fn test_run_inline() {
+        let sql =
+            "SELECT name, height FROM (VALUES ('Luke', 172), ('Leia', 150), ('Han', NULL)) AS people(name, height)";
+        let report = |rows_to_report| run_inline(sql, rows_to_report).expect("query should run");
+
+        // The columns are reported once, above the rows, and the row count last
+        assert_eq!(
+            report(10),
+            vec!["name | height", "Luke | 172", "Leia | 150", "Han | ", "3 rows"]
+        );
+
+        // Rows beyond plprql.inline_rows are counted but not reported
+        assert_eq!(report(2), vec!["name | height", "Luke | 172", "Leia | 150", "3 rows"]);
+        assert_eq!(report(0), vec!["3 rows"]);
+
+        assert_eq!(
+            run_inline("SELECT 1 AS n", 10).expect("query should run"),
+            vec!["n", "1", "1 row"]
+        );
+        assert_eq!(
+            run_inline("SELECT 1 AS n WHERE false", 10).expect("query should run"),
+            vec!["0 rows"]
+        );
+    }
 }
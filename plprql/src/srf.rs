@@ -1,32 +1,86 @@
-use crate::anydatum::AnyDatum;
-use crate::spi::Row;
+use crate::guc;
+use crate::spi::Cursor;
 use pgrx::callconv::FcInfo;
-use pgrx::{IntoDatum, IntoHeapTuple, pg_sys};
+use pgrx::prelude::*;
+use pgrx::{PgMemoryContexts, pg_sys};
 
 pub struct Table {
-    rows: Vec<Row>,
+    cursor: Cursor,
+    tuple_desc: pg_sys::TupleDesc,
+    batch_context: PgMemoryContexts,
+    rows: std::vec::IntoIter<pg_sys::Datum>,
 }
 
 impl Table {
-    // Get row for current state of stf_context
-    unsafe fn stf_return_at(srf_context: &mut pg_sys::FuncCallContext) -> &Row {
-        let index = srf_context.call_cntr as usize;
-        let table = unsafe { &mut *(srf_context.user_fctx as *mut Table) };
-        &table.rows[index]
+    // Get next row, fetching the next batch from the portal when the current batch is used up
+    fn next_row(&mut self) -> Option<pg_sys::Datum> {
+        loop {
+            if let Some(row) = self.rows.next() {
+                return Some(row);
+            }
+
+            if self.cursor.is_exhausted() {
+                return None;
+            }
+
+            // Copy rows as composite datums stamped with the caller's row type
+            let tuple_desc = self.tuple_desc;
+            self.rows = fetch_batch(&mut self.cursor, tuple_desc, &mut self.batch_context, |tuple| unsafe {
+                pg_sys::heap_copy_tuple_as_datum(tuple, tuple_desc)
+            });
+        }
     }
 }
 
 pub struct SetOf {
-    records: Vec<Option<AnyDatum>>,
+    cursor: Cursor,
+    tuple_desc: pg_sys::TupleDesc,
+    batch_context: PgMemoryContexts,
+    records: std::vec::IntoIter<pg_sys::HeapTuple>,
 }
 
 impl SetOf {
-    // Get record for current state of stf_context
-    unsafe fn srf_return_at(srf_context: &mut pg_sys::FuncCallContext) -> &Option<AnyDatum> {
-        let index = srf_context.call_cntr as usize;
-        let setof = unsafe { &mut *(srf_context.user_fctx as *mut SetOf) };
-        &setof.records[index]
+    // Get next record, fetching the next batch from the portal when the current batch is used up
+    fn next_record(&mut self) -> Option<Option<pg_sys::Datum>> {
+        loop {
+            if let Some(tuple) = self.records.next() {
+                let mut is_null = false;
+                let datum = unsafe { pg_sys::heap_getattr(tuple, 1, self.tuple_desc, &mut is_null) };
+                return Some((!is_null).then_some(datum));
+            }
+
+            if self.cursor.is_exhausted() {
+                return None;
+            }
+
+            self.records = fetch_batch(
+                &mut self.cursor,
+                self.tuple_desc,
+                &mut self.batch_context,
+                |tuple| unsafe { pg_sys::heap_copytuple(tuple) },
+            );
+        }
+    }
+}
+
+// Fetch the next batch from the portal and copy it into the batch memory context. The previous batch has been
+// returned to the caller by now, so its memory is reused.
+fn fetch_batch<T>(
+    cursor: &mut Cursor,
+    tuple_desc: pg_sys::TupleDesc,
+    batch_context: &mut PgMemoryContexts,
+    copy: impl Fn(pg_sys::HeapTuple) -> T,
+) -> std::vec::IntoIter<T> {
+    let mut batch = Vec::new();
+
+    unsafe {
+        batch_context.reset();
+        cursor.fetch(guc::FETCH_SIZE.get() as i64, tuple_desc, |tuple| {
+            batch.push(batch_context.switch_to(|_| copy(tuple)))
+        });
     }
+
+    batch.into_iter()
 }
 
 // Initialize tuple descriptor for table-returning functions
@@ -43,21 +97,59 @@ unsafe fn init_tuple_descriptor(fcinfo: &mut FcInfo) -> *mut pg_sys::TupleDescDa
     tupdesc
 }
 
+// Initialize a single-column tuple descriptor for functions returning sets of scalars
+unsafe fn init_scalar_tuple_descriptor(fcinfo: &mut FcInfo) -> *mut pg_sys::TupleDescData {
+    unsafe {
+        let mut type_oid = pg_sys::InvalidOid;
+        pg_sys::get_call_result_type(fcinfo.as_mut_ptr(), &mut type_oid, std::ptr::null_mut());
+
+        let tupdesc = pg_sys::CreateTemplateTupleDesc(1);
+        pg_sys::TupleDescInitEntry(tupdesc, 1, std::ptr::null(), type_oid, -1, 0);
+        tupdesc
+    }
+}
+
 // Get function context for subsequent SRF calls
 unsafe fn get_function_call_context<'fcx>(fcinfo: &FcInfo<'fcx>) -> &'fcx mut pg_sys::FuncCallContext {
     unsafe { &mut *pg_sys::per_MultiFuncCall(fcinfo.as_mut_ptr()) }
 }
 
-// Drop SRF state if present
-unsafe fn drop_srf_state<T>(srf_context: &mut pg_sys::FuncCallContext) {
-    if !srf_context.user_fctx.is_null() {
-        unsafe { drop(Box::from_raw(srf_context.user_fctx as *mut T)) };
+// Store SRF state and make sure it is dropped, and its portal closed, if the executor shuts down the SRF early, e.g.
+// because of a `limit` in the calling query
+unsafe fn init_srf_state<T>(fcinfo: &mut FcInfo, srf_context: &mut pg_sys::FuncCallContext, state: T) {
+    unsafe {
+        let return_set_info = (*fcinfo.as_mut_ptr()).resultinfo as *mut pg_sys::ReturnSetInfo;
+        srf_context.user_fctx = Box::into_raw(Box::new(state)) as *mut std::ffi::c_void;
+        pg_sys::RegisterExprContextCallback(
+            (*return_set_info).econtext,
+            Some(shutdown_srf_state::<T>),
+            pg_sys::Datum::from(srf_context.user_fctx),
+        );
+    }
+}
+
+#[pg_guard]
+unsafe extern "C-unwind" fn shutdown_srf_state<T>(arg: pg_sys::Datum) {
+    unsafe { drop(Box::from_raw(arg.cast_mut_ptr::<T>())) };
+}
+
+// Drop SRF state when all rows have been returned
+unsafe fn drop_srf_state<T>(fcinfo: &mut FcInfo, srf_context: &mut pg_sys::FuncCallContext) {
+    unsafe {
+        let return_set_info = (*fcinfo.as_mut_ptr()).resultinfo as *mut pg_sys::ReturnSetInfo;
+        pg_sys::UnregisterExprContextCallback(
+            (*return_set_info).econtext,
+            Some(shutdown_srf_state::<T>),
+            pg_sys::Datum::from(srf_context.user_fctx),
+        );
+        drop(Box::from_raw(srf_context.user_fctx as *mut T));
+        srf_context.user_fctx = std::ptr::null_mut();
     }
 }
 
-pub unsafe fn table_srf_next<F>(function_call_info: pg_sys::FunctionCallInfo, fetch_rows: F) -> pg_sys::Datum
+pub unsafe fn table_srf_next<F>(function_call_info: pg_sys::FunctionCallInfo, open_cursor: F) -> pg_sys::Datum
 where
-    F: FnOnce() -> Option<Vec<Row>>,
+    F: FnOnce() -> Cursor,
 {
     unsafe {
         let mut fcinfo = FcInfo::from_ptr(function_call_info);
@@ -74,40 +166,35 @@ where
                 srf_context.tuple_desc = init_tuple_descriptor(&mut fcinfo);
 
                 // Setup state
-                if let Some(rows) = fetch_rows() {
-                    let table = Box::new(Table { rows });
-                    srf_context.max_calls = table.rows.len() as u64;
-                    srf_context.user_fctx = Box::into_raw(table) as *mut std::ffi::c_void;
-                } else {
-                    srf_context.max_calls = 0;
-                }
+                let table = Table {
+                    cursor: open_cursor(),
+                    tuple_desc: srf_context.tuple_desc,
+                    batch_context: PgMemoryContexts::new("plprql batch"),
+                    rows: Vec::new().into_iter(),
+                };
+                init_srf_state(&mut fcinfo, srf_context, table);
 
                 pg_sys::MemoryContextSwitchTo(old_context);
                 srf_context
             }
         };
 
-        // Clean up if we've returned all rows
-        if srf_context.call_cntr >= srf_context.max_calls {
-            drop_srf_state::<Table>(srf_context);
+        // Get next row, or clean up if we've returned all rows
+        let table = &mut *(srf_context.user_fctx as *mut Table);
+        let Some(row) = table.next_row() else {
+            drop_srf_state::<Table>(&mut fcinfo, srf_context);
             fcinfo.srf_return_done();
             return pg_sys::Datum::from(0);
-        }
+        };
 
-        // Get row at stf_context.call_cntr and increment for next call
-        let row = Table::stf_return_at(srf_context);
         fcinfo.srf_return_next();
-
-        // Convert to datum
-        let heap_tuple = row.clone().into_heap_tuple(srf_context.tuple_desc);
-        let datum = pg_sys::HeapTupleHeaderGetDatum((*heap_tuple).t_data);
-        fcinfo.return_raw_datum(datum).sans_lifetime()
+        fcinfo.return_raw_datum(row).sans_lifetime()
     }
 }
 
-pub unsafe fn setof_srf_next<F>(function_call_info: pg_sys::FunctionCallInfo, fetch_records: F) -> pg_sys::Datum
+pub unsafe fn setof_srf_next<F>(function_call_info: pg_sys::FunctionCallInfo, open_cursor: F) -> pg_sys::Datum
 where
-    F: FnOnce() -> Option<Vec<Option<AnyDatum>>>,
+    F: FnOnce() -> Cursor,
 {
     unsafe {
         let mut fcinfo = FcInfo::from_ptr(function_call_info);
@@ -125,39 +212,121 @@ where
                 return_set_info.set_return_mode(pg_sys::SetFunctionReturnMode::SFRM_ValuePerCall);
 
                 // Setup state
-                if let Some(records) = fetch_records() {
-                    let setof = Box::new(SetOf { records });
-                    srf_context.max_calls = setof.records.len() as u64;
-                    srf_context.user_fctx = Box::into_raw(setof) as *mut std::ffi::c_void;
-                } else {
-                    srf_context.max_calls = 0;
-                }
+                let setof = SetOf {
+                    cursor: open_cursor(),
+                    tuple_desc: init_scalar_tuple_descriptor(&mut fcinfo),
+                    batch_context: PgMemoryContexts::new("plprql batch"),
+                    records: Vec::new().into_iter(),
+                };
+                init_srf_state(&mut fcinfo, srf_context, setof);
 
                 pg_sys::MemoryContextSwitchTo(old_context);
                 srf_context
             }
         };
 
-        // Clean up if we've returned all records
-        if srf_context.call_cntr >= srf_context.max_calls {
-            drop_srf_state::<SetOf>(srf_context);
+        // Get next record, or clean up if we've returned all records
+        let setof = &mut *(srf_context.user_fctx as *mut SetOf);
+        let Some(record) = setof.next_record() else {
+            drop_srf_state::<SetOf>(&mut fcinfo, srf_context);
             fcinfo.srf_return_done();
             return pg_sys::Datum::from(0);
-        }
+        };
 
-        // Get record at stf_context.call_cntr and increment for next call
-        let record = SetOf::srf_return_at(srf_context);
         fcinfo.srf_return_next();
 
-        // Convert to datum
         let datum = match record {
-            Some(value) => {
-                let datum = value.clone().into_datum().unwrap_or(pg_sys::Datum::from(0));
-                fcinfo.return_raw_datum(datum)
-            }
+            Some(datum) => fcinfo.return_raw_datum(datum),
             None => fcinfo.return_null(),
         };
 
         datum.sans_lifetime()
     }
 }
+
+// Whether to return results in a tuplestore rather than row by row. Rows are streamed unless the caller only accepts a
+// tuplestore or `plprql.materialize` is on, as a tuplestore holds the whole result before the caller sees any of it.
+pub unsafe fn uses_materialize(function_call_info: pg_sys::FunctionCallInfo) -> bool {
+    unsafe {
+        let return_set_info = (*function_call_info).resultinfo as *mut pg_sys::ReturnSetInfo;
+        if return_set_info.is_null()
+            || !pgrx::is_a(return_set_info as *mut pg_sys::Node, pg_sys::NodeTag::T_ReturnSetInfo)
+        {
+            return false;
+        }
+
+        let allows = |mode: pg_sys::SetFunctionReturnMode::Type| (*return_set_info).allowedModes & mode as i32 != 0;
+        allows(pg_sys::SetFunctionReturnMode::SFRM_Materialize)
+            && (guc::MATERIALIZE.get() || !allows(pg_sys::SetFunctionReturnMode::SFRM_ValuePerCall))
+    }
+}
+
+// Create the tuplestore the caller reads results from once the function returns. The tuplestore lives in the
+// per-query memory context and spills to disk when it outgrows work_mem.
+unsafe fn init_tuple_store(
+    return_set_info: *mut pg_sys::ReturnSetInfo,
+    tuple_desc: *mut pg_sys::TupleDescData,
+) -> *mut pg_sys::Tuplestorestate {
+    unsafe {
+        let random_access =
+            (*return_set_info).allowedModes & pg_sys::SetFunctionReturnMode::SFRM_Materialize_Random as i32 != 0;
+        let tuple_store = pg_sys::tuplestore_begin_heap(random_access, false, pg_sys::work_mem);
+
+        (*return_set_info).returnMode = pg_sys::SetFunctionReturnMode::SFRM_Materialize;
+        (*return_set_info).setResult = tuple_store;
+        (*return_set_info).setDesc = tuple_desc;
+        tuple_store
+    }
+}
+
+// Fetch batches from the portal into the tuplestore until it is exhausted. The tuplestore copies tuples straight
+// out of SPI memory.
+unsafe fn fill_tuple_store(
+    cursor: &mut Cursor,
+    tuple_store: *mut pg_sys::Tuplestorestate,
+    tuple_desc: *mut pg_sys::TupleDescData,
+) {
+    while !cursor.is_exhausted() {
+        cursor.fetch(guc::FETCH_SIZE.get() as i64, tuple_desc, |tuple| unsafe {
+            pg_sys::tuplestore_puttuple(tuple_store, tuple)
+        });
+    }
+}
+
+pub unsafe fn table_srf_materialize<F>(function_call_info: pg_sys::FunctionCallInfo, open_cursor: F) -> pg_sys::Datum
+where
+    F: FnOnce() -> Cursor,
+{
+    unsafe {
+        let mut fcinfo = FcInfo::from_ptr(function_call_info);
+        let return_set_info = (*function_call_info).resultinfo as *mut pg_sys::ReturnSetInfo;
+
+        // The tuple descriptor and tuplestore must outlive the function call
+        let old_context = pg_sys::MemoryContextSwitchTo((*(*return_set_info).econtext).ecxt_per_query_memory);
+        let tuple_desc = init_tuple_descriptor(&mut fcinfo);
+        let tuple_store = init_tuple_store(return_set_info, tuple_desc);
+        pg_sys::MemoryContextSwitchTo(old_context);
+
+        fill_tuple_store(&mut open_cursor(), tuple_store, tuple_desc);
+        pg_sys::Datum::from(0)
+    }
+}
+
+pub unsafe fn setof_srf_materialize<F>(function_call_info: pg_sys::FunctionCallInfo, open_cursor: F) -> pg_sys::Datum
+where
+    F: FnOnce() -> Cursor,
+{
+    unsafe {
+        let mut fcinfo = FcInfo::from_ptr(function_call_info);
+        let return_set_info = (*function_call_info).resultinfo as *mut pg_sys::ReturnSetInfo;
+
+        // The tuple descriptor and tuplestore must outlive the function call
+        let old_context = pg_sys::MemoryContextSwitchTo((*(*return_set_info).econtext).ecxt_per_query_memory);
+        let tuple_desc = init_scalar_tuple_descriptor(&mut fcinfo);
+        let tuple_store = init_tuple_store(return_set_info, tuple_desc);
+        pg_sys::MemoryContextSwitchTo(old_context);
+
+        fill_tuple_store(&mut open_cursor(), tuple_store, tuple_desc);
+        pg_sys::Datum::from(0)
+    }
+}
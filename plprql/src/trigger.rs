@@ -0,0 +1,60 @@
+use pgrx::datum::DatumWithOid;
+use pgrx::prelude::*;
+
+// Triggers pass the rows they fire for to the query as parameters, so the body can use `new` and `old` as relations of
+// a single row, or of no row when the operation has no such row, e.g. `old` on insert. The parameters have the
+// table's row type, so a function used by triggers on several tables is prepared for each table in turn.
+pub(crate) fn wrap(sql: &str) -> String {
+    format!(
+        "WITH \"new\" AS (SELECT ($1).* WHERE $3), \"old\" AS (SELECT ($2).* WHERE $4) SELECT * FROM ({sql}) AS result"
+    )
+}
+
+pub(crate) fn argument_types(trigger_data: *mut pg_sys::TriggerData) -> Vec<PgOid> {
+    let row_type = unsafe { (*(*(*trigger_data).tg_relation).rd_rel).reltype };
+    vec![
+        PgOid::from(row_type),
+        PgOid::from(row_type),
+        PgOid::from(pg_sys::BOOLOID),
+        PgOid::from(pg_sys::BOOLOID),
+    ]
+}
+
+pub(crate) fn arguments(trigger_data: *mut pg_sys::TriggerData) -> Vec<DatumWithOid<'static>> {
+    unsafe {
+        let relation = (*trigger_data).tg_relation;
+        let row_type = (*(*relation).rd_rel).reltype;
+        let (new, old) = rows(trigger_data);
+
+        let row = |tuple: pg_sys::HeapTuple| match tuple.is_null() {
+            true => DatumWithOid::null_oid(row_type),
+            false => DatumWithOid::new(pg_sys::heap_copy_tuple_as_datum(tuple, (*relation).rd_att), row_type),
+        };
+
+        vec![
+            row(new),
+            row(old),
+            DatumWithOid::from(!new.is_null()),
+            DatumWithOid::from(!old.is_null()),
+        ]
+    }
+}
+
+// The new and old row of a row-level trigger, or null if the operation has no such row. Statement-level triggers have
+// neither.
+fn rows(trigger_data: *mut pg_sys::TriggerData) -> (pg_sys::HeapTuple, pg_sys::HeapTuple) {
+    let none = std::ptr::null_mut();
+    unsafe {
+        let event = (*trigger_data).tg_event;
+        if event & pg_sys::TRIGGER_EVENT_ROW == 0 {
+            return (none, none);
+        }
+
+        match event & pg_sys::TRIGGER_EVENT_OPMASK {
+            pg_sys::TRIGGER_EVENT_INSERT => ((*trigger_data).tg_trigtuple, none),
+            pg_sys::TRIGGER_EVENT_UPDATE => ((*trigger_data).tg_newtuple, (*trigger_data).tg_trigtuple),
+            pg_sys::TRIGGER_EVENT_DELETE => (none, (*trigger_data).tg_trigtuple),
+            _ => (none, none),
+        }
+    }
+}